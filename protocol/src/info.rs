@@ -0,0 +1,100 @@
+use crate::table::{from_unix, to_unix};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+/// Server state returned by `INFO`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+  pub version: String,
+  // seconds since the server started
+  pub uptime: u64,
+  pub limits: Limits,
+  pub connections: Connections,
+  // key counts of the tables the client can read
+  pub tables: BTreeMap<String, u64>,
+  // approximate bytes used by the database and connection buffers, for admins only
+  pub memory: Option<u64>,
+  pub persistence: Persistence,
+}
+
+/// A request that took longer than the server's slow log threshold.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SlowRequest {
+  // increases with each slow request, even across resets
+  pub id: u64,
+  #[serde(deserialize_with = "from_unix", serialize_with = "to_unix")]
+  pub time: Option<SystemTime>,
+  // microseconds
+  pub duration: u64,
+  pub action: String,
+  pub table: Option<String>,
+  pub key: Option<String>,
+  pub peer: String,
+  pub user: Option<String>,
+  // bytes, as received
+  pub size: u64,
+}
+
+/// A connection returned by `CLIENT LIST`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+  pub id: u64,
+  pub peer: String,
+  pub user: Option<String>,
+  // address the connection was accepted on
+  pub listener: String,
+  pub tls: bool,
+  #[serde(deserialize_with = "from_unix", serialize_with = "to_unix")]
+  pub since: Option<SystemTime>,
+  // action of the last request handled
+  pub last_command: Option<String>,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  // bytes held by the connection's receive buffer
+  pub buffer_memory: u64,
+}
+
+/// A request seen by a `MONITOR` connection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MonitorEvent {
+  // seconds since the Unix epoch
+  pub time: f64,
+  pub peer: String,
+  pub user: Option<String>,
+  pub action: String,
+  pub table: Option<String>,
+  pub key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Limits {
+  pub max_conns: u64,
+  pub max_message_size: u64,
+  pub compress_threshold: u64,
+  // seconds, null if disabled
+  pub idle_timeout: Option<u64>,
+  pub read_timeout: Option<u64>,
+  pub write_timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Connections {
+  pub current: u64,
+  pub total: u64,
+  pub rejected: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Persistence {
+  // seconds
+  pub autosave_interval: u64,
+  // null if the server hasn't saved since it started
+  #[serde(deserialize_with = "from_unix", serialize_with = "to_unix")]
+  pub last_save: Option<SystemTime>,
+  // seconds
+  pub last_save_duration: f64,
+  pub last_save_size: u64,
+  // whether saves are keeping up with the autosave interval
+  pub healthy: bool,
+}
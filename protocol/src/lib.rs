@@ -1,7 +1,11 @@
+mod info;
+mod pubsub;
 mod request;
 mod response;
 mod table;
 
+pub use info::*;
+pub use pubsub::*;
 pub use request::*;
 pub use response::*;
 pub use table::*;
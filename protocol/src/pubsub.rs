@@ -0,0 +1,35 @@
+use crate::PrimitiveValue;
+use serde::{Deserialize, Serialize};
+
+/// A published message, pushed to each subscribed connection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+  pub channel: String,
+  // the subscribed pattern that matched, if it wasn't an exact subscription
+  pub pattern: Option<String>,
+  pub data: PrimitiveValue,
+}
+
+/// What changed in a keyspace event, named after the request that usually causes it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum KeyspaceAction {
+  Insert,
+  // an existing value was modified in place
+  Update,
+  Delete,
+  Expire,
+  #[serde(rename = "INSERT TABLE")]
+  InsertTable,
+  #[serde(rename = "DELETE TABLE")]
+  DeleteTable,
+}
+
+/// A change to a table or key, pushed to connections subscribed to the keyspace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyspaceEvent {
+  pub action: KeyspaceAction,
+  pub table: String,
+  // null for table events
+  pub key: Option<String>,
+}
@@ -1,6 +1,14 @@
-use crate::{InsertTable, InsertTableValue};
+use crate::{InsertTable, InsertTableValue, PrimitiveValue};
 use serde::{Deserialize, Serialize};
 
+/// Which end of a list to push to or pop from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ListEnd {
+  Front,
+  Back,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "action")]
 #[serde(rename_all = "UPPERCASE")]
@@ -11,6 +19,64 @@ pub enum Request {
     username: String,
     password: String,
   },
+  #[serde(rename = "COMPRESS STREAM")]
+  CompressStream {
+    mode: u8,
+  },
+  Info,
+
+  // ADMINISTRATION
+  #[serde(rename = "SLOWLOG GET")]
+  SlowLogGet {
+    // newest first, everything if null
+    count: Option<u64>,
+  },
+  #[serde(rename = "SLOWLOG RESET")]
+  SlowLogReset,
+  // stream processed requests until the connection closes, sending again replaces the filters
+  Monitor {
+    // glob pattern, all tables if null
+    table: Option<String>,
+    // all actions if null
+    actions: Option<Vec<String>>,
+  },
+  #[serde(rename = "CLIENT LIST")]
+  ClientList,
+  // closes every connection matching all given fields, at least one is required
+  #[serde(rename = "CLIENT KILL")]
+  ClientKill {
+    id: Option<u64>,
+    peer: Option<String>,
+    user: Option<String>,
+  },
+
+  // PUB/SUB
+  Subscribe {
+    channels: Option<Vec<String>>,
+    // glob patterns
+    patterns: Option<Vec<String>>,
+  },
+  // from everything if both are null
+  Unsubscribe {
+    channels: Option<Vec<String>>,
+    patterns: Option<Vec<String>>,
+  },
+  Publish {
+    channel: String,
+    data: PrimitiveValue,
+  },
+  #[serde(rename = "KEYSPACE SUBSCRIBE")]
+  KeyspaceSubscribe {
+    // glob patterns, all keys if null
+    table: String,
+    key: Option<String>,
+  },
+  // from every keyspace subscription if null
+  #[serde(rename = "KEYSPACE UNSUBSCRIBE")]
+  KeyspaceUnsubscribe {
+    table: Option<String>,
+    key: Option<String>,
+  },
 
   // TABLE OPERATIONS
   #[serde(rename = "LIST TABLE")]
@@ -41,10 +107,173 @@ pub enum Request {
     table: String,
     key: String,
   },
+  // blocks until the key changes, or `timeout` seconds pass if set
+  Watch {
+    table: String,
+    key: String,
+    timeout: Option<u64>,
+  },
   Insert {
     table: String,
     key: String,
     #[serde(flatten)]
     value: InsertTableValue,
   },
+
+  // LIST OPERATIONS
+  // on array values, a missing key is created by PUSH
+  Push {
+    table: String,
+    key: String,
+    end: ListEnd,
+    // pushed in order
+    values: Vec<PrimitiveValue>,
+  },
+  Pop {
+    table: String,
+    key: String,
+    end: ListEnd,
+    // 1 if null
+    count: Option<u64>,
+  },
+  // blocks until there's a value to pop, or `timeout` seconds pass if set
+  #[serde(rename = "BLOCKING POP")]
+  BlockingPop {
+    table: String,
+    key: String,
+    end: ListEnd,
+    timeout: Option<u64>,
+  },
+  // indices are inclusive, negative ones count back from the end
+  Range {
+    table: String,
+    key: String,
+    start: i64,
+    stop: i64,
+  },
+  // keeps only the given range
+  Trim {
+    table: String,
+    key: String,
+    start: i64,
+    stop: i64,
+  },
+  Length {
+    table: String,
+    key: String,
+  },
+}
+
+impl Request {
+  /// Every `action` tag, indexed by [`Request::action_id`].
+  pub const ACTIONS: [&'static str; 29] = [
+    "PING",
+    "AUTH",
+    "COMPRESS STREAM",
+    "INFO",
+    "SLOWLOG GET",
+    "SLOWLOG RESET",
+    "MONITOR",
+    "CLIENT LIST",
+    "CLIENT KILL",
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUBLISH",
+    "KEYSPACE SUBSCRIBE",
+    "KEYSPACE UNSUBSCRIBE",
+    "LIST TABLE",
+    "INSERT TABLE",
+    "GET TABLE",
+    "DELETE TABLE",
+    "LIST",
+    "GET",
+    "DELETE",
+    "WATCH",
+    "INSERT",
+    "PUSH",
+    "POP",
+    "BLOCKING POP",
+    "RANGE",
+    "TRIM",
+    "LENGTH",
+  ];
+
+  /// The `action` tag this request is sent with.
+  #[inline]
+  pub fn action(&self) -> &'static str {
+    Self::ACTIONS[self.action_id()]
+  }
+
+  /// The index of this request's `action` in [`Request::ACTIONS`].
+  pub fn action_id(&self) -> usize {
+    match self {
+      Self::Ping => 0,
+      Self::Auth { .. } => 1,
+      Self::CompressStream { .. } => 2,
+      Self::Info => 3,
+      Self::SlowLogGet { .. } => 4,
+      Self::SlowLogReset => 5,
+      Self::Monitor { .. } => 6,
+      Self::ClientList => 7,
+      Self::ClientKill { .. } => 8,
+      Self::Subscribe { .. } => 9,
+      Self::Unsubscribe { .. } => 10,
+      Self::Publish { .. } => 11,
+      Self::KeyspaceSubscribe { .. } => 12,
+      Self::KeyspaceUnsubscribe { .. } => 13,
+      Self::ListTables => 14,
+      Self::InsertTable { .. } => 15,
+      Self::GetTable { .. } => 16,
+      Self::DeleteTable { .. } => 17,
+      Self::List { .. } => 18,
+      Self::Get { .. } => 19,
+      Self::Delete { .. } => 20,
+      Self::Watch { .. } => 21,
+      Self::Insert { .. } => 22,
+      Self::Push { .. } => 23,
+      Self::Pop { .. } => 24,
+      Self::BlockingPop { .. } => 25,
+      Self::Range { .. } => 26,
+      Self::Trim { .. } => 27,
+      Self::Length { .. } => 28,
+    }
+  }
+
+  /// The table this request operates on, if any.
+  pub fn table(&self) -> Option<&str> {
+    match self {
+      Self::InsertTable { table, .. }
+      | Self::GetTable { table }
+      | Self::DeleteTable { table }
+      | Self::List { table }
+      | Self::Get { table, .. }
+      | Self::Delete { table, .. }
+      | Self::Watch { table, .. }
+      | Self::Insert { table, .. }
+      | Self::Push { table, .. }
+      | Self::Pop { table, .. }
+      | Self::BlockingPop { table, .. }
+      | Self::Range { table, .. }
+      | Self::Trim { table, .. }
+      | Self::Length { table, .. } => Some(table),
+      _ => None,
+    }
+  }
+
+  /// The key this request operates on, if any.
+  pub fn key(&self) -> Option<&str> {
+    match self {
+      Self::Get { key, .. }
+      | Self::Delete { key, .. }
+      | Self::Watch { key, .. }
+      | Self::Insert { key, .. }
+      | Self::Push { key, .. }
+      | Self::Pop { key, .. }
+      | Self::BlockingPop { key, .. }
+      | Self::Range { key, .. }
+      | Self::Trim { key, .. }
+      | Self::Length { key, .. } => Some(key),
+      _ => None,
+    }
+  }
 }
@@ -1,4 +1,7 @@
-use crate::{Table, TableValue};
+use crate::{
+  ClientInfo, KeyspaceEvent, Message, MonitorEvent, PrimitiveValue, ServerInfo, SlowRequest, Table,
+  TableValue,
+};
 use serde::{Deserialize, Serialize};
 
 /// Response status. Also functions as an Error type.
@@ -19,6 +22,12 @@ pub enum Status {
   #[serde(rename = "Response too large")]
   ResponseTooLarge,
 
+  // THROTTLING
+  #[serde(rename = "Rate limited")]
+  RateLimited,
+  #[serde(rename = "Too many subscriptions")]
+  SubscriptionLimit,
+
   // AUTH
   Unauthorized,
   #[serde(rename = "Permission denied")]
@@ -33,10 +42,43 @@ pub enum Status {
   NoSuchKey,
   #[serde(rename = "Key expired")]
   KeyExpired,
+  // a list operation on a value that isn't an array
+  #[serde(rename = "Wrong type")]
+  WrongType,
+  // nothing happened before a blocking request timed out
+  #[serde(rename = "Timed out")]
+  WaitTimedOut,
 }
 
 pub use Status::*;
 
+impl Status {
+  pub const ALL: [Self; 16] = [
+    Success,
+    ConnLimit,
+    BadRequest,
+    ServerError,
+    RequestTooLarge,
+    ResponseTooLarge,
+    RateLimited,
+    SubscriptionLimit,
+    Unauthorized,
+    PermissionDenied,
+    AlreadyExists,
+    NoSuchTable,
+    NoSuchKey,
+    KeyExpired,
+    WrongType,
+    WaitTimedOut,
+  ];
+
+  /// The index of this status in [`Status::ALL`].
+  #[inline]
+  pub fn id(self) -> usize {
+    self as usize
+  }
+}
+
 impl std::fmt::Display for Status {
   #[inline]
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -71,6 +113,44 @@ pub enum Payload {
     key: String,
     value: TableValue,
   },
+  // values popped or read from a list
+  Values {
+    values: Vec<PrimitiveValue>,
+  },
+  Length {
+    length: u64,
+  },
+  Info {
+    info: ServerInfo,
+  },
+  SlowLog {
+    slowlog: Vec<SlowRequest>,
+  },
+  Clients {
+    clients: Vec<ClientInfo>,
+  },
+  Killed {
+    killed: u64,
+  },
+  // subscriptions held by the connection
+  Subscriptions {
+    subscriptions: u64,
+  },
+  // subscribers a message was queued for
+  Receivers {
+    receivers: u64,
+  },
+  // pushed to monitoring connections, not a response to a request
+  Monitor {
+    monitor: MonitorEvent,
+  },
+  // pushed to subscribed connections
+  Message {
+    message: Message,
+  },
+  Keyspace {
+    keyspace: KeyspaceEvent,
+  },
 }
 
 // RESPONSE
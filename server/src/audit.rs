@@ -0,0 +1,163 @@
+use crate::auth::User;
+use crate::config::AuditConfig;
+use crate::connection::Peer;
+use crate::{logger::*, wrap_fatal, Global};
+use protocol::{Request, Status};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::SystemTime;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{channel, Sender};
+
+// SINK
+
+// serialized lines, written in order by a single task
+// bounded, so requests wait for a slow disk instead of queueing without limit
+static SINK: Global<Sender<String>> = Global::new();
+static INCLUDE_VALUES: Global<bool> = Global::new();
+
+/// Open the audit log and start writing events to it.
+pub async fn init(conf: &AuditConfig) {
+  let mut opts = OpenOptions::new();
+  let file = opts.create(true).append(true).open(&conf.path).await;
+  let mut file = wrap_fatal!(file, "Failed to open audit log: {}");
+  info!("Writing audit log to {}", conf.path);
+
+  let (tx, mut rx) = channel::<String>(conf.queue.max(1));
+  SINK.set(tx);
+  INCLUDE_VALUES.set(conf.values);
+
+  tokio::spawn(async move {
+    while let Some(line) = rx.recv().await {
+      // flushed per event, so nothing is lost if we exit
+      let res = file.write_all(line.as_bytes()).await;
+      if let Err(e) = res.and(file.flush().await) {
+        error!("Failed to write audit log: {}", e);
+      }
+    }
+  });
+}
+
+async fn write(event: &Event) {
+  match serde_json::to_string(event) {
+    Ok(mut line) => {
+      line.push('\n');
+      let _ = SINK.send(line).await;
+    }
+    Err(e) => error!("Failed to serialize audit event: {}", e),
+  }
+}
+
+// EVENTS
+
+#[derive(Serialize, Debug)]
+struct Event {
+  // when the request arrived
+  time: String,
+  peer: String,
+  user: Option<String>,
+  action: &'static str,
+  status: Status,
+  // how a connection was authenticated without AUTH, only for `CONNECT`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  auth: Option<&'static str>,
+  table: Option<String>,
+  key: Option<String>,
+  // omitted unless values are included
+  #[serde(skip_serializing_if = "Option::is_none")]
+  value: Option<Value>,
+}
+
+#[inline]
+fn now() -> String {
+  humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+/// Record a connection authenticated as `user` by `auth`, such as its certificate, before any request.
+pub async fn connected(peer: Peer, user: User, auth: &'static str) {
+  if SINK.get().is_none() {
+    return;
+  }
+  let event = Event {
+    time: now(),
+    peer: peer.to_string(),
+    user: Some(user.name.to_owned()),
+    action: "CONNECT",
+    status: Status::Success,
+    auth: Some(auth),
+    table: None,
+    key: None,
+    value: None,
+  };
+  write(&event).await;
+}
+
+/// A pending audit event for a request, written once its outcome is known.
+#[derive(Default)]
+pub struct Audit(Option<Event>);
+
+impl Audit {
+  /// Start an event if auditing is enabled and `req` is audited.
+  pub fn new(req: &Request, peer: Peer, user: Option<User>) -> Self {
+    if SINK.get().is_none() {
+      return Self(None);
+    }
+
+    let values = *INCLUDE_VALUES;
+    let name = user.map(|u| u.name);
+    let (user, table, key, value) = match req {
+      // the attempted username, not the current one
+      Request::Auth { username, .. } => (Some(username.as_str()), None, None, None),
+      Request::InsertTable { table, contents } => {
+        let v = values.then(|| json!(contents));
+        (name, Some(table), None, v)
+      }
+      Request::DeleteTable { table } => (name, Some(table), None, None),
+      Request::Insert {
+        table,
+        key,
+        value: v,
+      } => {
+        let v = values.then(|| json!(v));
+        (name, Some(table), Some(key), v)
+      }
+      Request::Delete { table, key } => (name, Some(table), Some(key), None),
+      Request::Push {
+        table,
+        key,
+        values: v,
+        ..
+      } => {
+        let v = values.then(|| json!(v));
+        (name, Some(table), Some(key), v)
+      }
+      Request::Pop { table, key, .. }
+      | Request::BlockingPop { table, key, .. }
+      | Request::Trim { table, key, .. } => (name, Some(table), Some(key), None),
+      _ => return Self(None),
+    };
+
+    Self(Some(Event {
+      time: now(),
+      peer: peer.to_string(),
+      user: user.map(str::to_owned),
+      action: req.action(),
+      status: Status::Success,
+      auth: None,
+      table: table.cloned(),
+      key: key.cloned(),
+      value,
+    }))
+  }
+
+  /// Write the event with its outcome. Later calls do nothing.
+  pub async fn log(&mut self, status: Status) {
+    let Some(mut event) = self.0.take() else {
+      return;
+    };
+    event.status = status;
+    write(&event).await;
+  }
+}
@@ -0,0 +1,346 @@
+use crate::config::{Role, UserConfig, CONFIG};
+use crate::connection::Peer;
+use crate::{glob_match, logger::*, AnyResult};
+use protocol::Request;
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use scc::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{LazyLock, OnceLock};
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, sleep, Duration, Instant};
+
+// PASSWORDS
+
+pub const DEFAULT_USERNAME: &str = "admin";
+pub const DEFAULT_PASSWORD: &str = "password";
+
+/// Hash a password into a PHC string for storage in the config.
+pub fn hash_password(password: &str) -> AnyResult<String> {
+  let salt = SaltString::generate(&mut OsRng);
+  let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+  Ok(hash.to_string())
+}
+
+#[inline]
+pub fn is_hashed(stored: &str) -> bool {
+  PasswordHash::new(stored).is_ok()
+}
+
+/// Check a password against a stored PHC hash. Plain text ones are hashed when users are loaded.
+pub fn verify_password(stored: &str, password: &str) -> bool {
+  PasswordHash::new(stored).is_ok_and(|hash| {
+    Argon2::default()
+      .verify_password(password.as_bytes(), &hash)
+      .is_ok()
+  })
+}
+
+// verified against for unknown users, so they take as long as known ones
+fn dummy_hash() -> &'static str {
+  static DUMMY: OnceLock<String> = OnceLock::new();
+  DUMMY.get_or_init(|| hash_password(DEFAULT_PASSWORD).unwrap_or_default())
+}
+
+/// Whether the default account is still configured with the default password.
+pub fn default_credentials() -> bool {
+  let user = User::get(DEFAULT_USERNAME);
+  user.is_some_and(|u| verify_password(&u.conf.password, DEFAULT_PASSWORD))
+}
+
+// USERS
+
+/// An authenticated user, borrowed from the global config.
+#[derive(Debug, Clone, Copy)]
+pub struct User {
+  pub name: &'static str,
+  pub conf: &'static UserConfig,
+}
+
+impl User {
+  /// Look up a configured user by name, without checking credentials.
+  #[inline]
+  pub fn get(name: &str) -> Option<Self> {
+    let (name, conf) = CONFIG.users.get_key_value(name)?;
+    Some(Self { name, conf })
+  }
+
+  /// The highest role granted on `table`, if any.
+  pub fn role(&self, table: &str) -> Option<Role> {
+    let grants = self.conf.grants.iter();
+    let matching = grants.filter(|(pattern, _)| glob_match(pattern, table));
+    matching.map(|(_, role)| *role).max()
+  }
+
+  #[inline]
+  pub fn can(&self, role: Role, table: &str) -> bool {
+    self.role(table).is_some_and(|r| r >= role)
+  }
+
+  /// Whether the user is an admin of every table, and so of the server itself.
+  #[inline]
+  pub fn is_admin(&self) -> bool {
+    self.conf.grants.get("*") == Some(&Role::Admin)
+  }
+}
+
+/// Check credentials off the async runtime, since hashing is deliberately slow.
+pub async fn authenticate(username: String, password: String) -> Option<User> {
+  let verify = move || {
+    let user = User::get(&username);
+    let stored = user.map_or_else(dummy_hash, |u| &u.conf.password);
+    let valid = verify_password(stored, &password);
+    user.filter(|_| valid)
+  };
+  spawn_blocking(verify).await.ok().flatten()
+}
+
+// BRUTE-FORCE PROTECTION
+
+#[derive(Debug, Clone, Copy)]
+struct IpFailures {
+  count: u32,
+  since: Instant,
+  locked_until: Option<Instant>,
+}
+
+static IP_FAILURES: LazyLock<HashMap<IpAddr, IpFailures>> = LazyLock::new(HashMap::default);
+
+/// Number of lockouts triggered since startup.
+pub static LOCKOUTS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether AUTH from `peer` is refused without checking credentials.
+pub fn locked_out(peer: Peer) -> bool {
+  let Some(ip) = peer.ip() else {
+    return false;
+  };
+  let now = Instant::now();
+  IP_FAILURES
+    .read(&ip, |_, f| f.locked_until.is_some_and(|t| t > now))
+    .unwrap_or_default()
+}
+
+/// Record a failed AUTH, then wait out the progressive delay.
+/// Returns false if the connection should be closed.
+pub async fn auth_failed(peer: Peer, failures: u32) -> bool {
+  let conf = &CONFIG.auth_limit;
+  let now = Instant::now();
+
+  if let (Some(ip), Some(max)) = (peer.ip(), conf.ip_max_failures) {
+    let window = Duration::from_secs(conf.window);
+    let mut entry = IP_FAILURES.entry_async(ip).await.or_insert(IpFailures {
+      count: 0,
+      since: now,
+      locked_until: None,
+    });
+    let f = entry.get_mut();
+    if now.duration_since(f.since) > window {
+      *f = IpFailures {
+        count: 0,
+        since: now,
+        locked_until: None,
+      };
+    }
+    f.count += 1;
+    if f.count >= max && f.locked_until.is_none_or(|t| t <= now) {
+      f.locked_until = Some(now + Duration::from_secs(conf.lockout));
+      LOCKOUTS.fetch_add(1, Relaxed);
+      warn!(
+        "Locking out {} for {}s after {} failed AUTH attempts",
+        ip, conf.lockout, f.count
+      );
+    }
+  }
+
+  let delay = conf
+    .delay
+    .saturating_mul(1 << failures.saturating_sub(1).min(16));
+  sleep(Duration::from_millis(delay.min(conf.max_delay))).await;
+
+  conf.max_failures.is_none_or(|max| failures < max)
+}
+
+/// Periodically forget IPs whose window and lockout have passed.
+pub async fn cleanup() {
+  let conf = &CONFIG.auth_limit;
+  let window = Duration::from_secs(conf.window);
+  let mut interval = interval(Duration::from_secs(60));
+  loop {
+    interval.tick().await;
+    let now = Instant::now();
+    let active = |f: &IpFailures| {
+      now.duration_since(f.since) <= window || f.locked_until.is_some_and(|t| t > now)
+    };
+    IP_FAILURES.retain_async(|_, f| active(f)).await;
+  }
+}
+
+// PERMISSIONS
+
+/// What a request requires before it can be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access<'a> {
+  Public,
+  Authenticated,
+  // server-wide actions, for users granted admin on `*`
+  Admin,
+  Table(Role, &'a str),
+}
+
+pub fn access(req: &Request) -> Access<'_> {
+  match req {
+    Request::Ping | Request::Auth { .. } => Access::Public,
+    // codec state isn't cheap, so only allocated for known users
+    Request::CompressStream { .. } => Access::Authenticated,
+    // only tables the user can read are listed
+    Request::ListTables | Request::Info => Access::Authenticated,
+    // channels are shared by all users
+    Request::Subscribe { .. } | Request::Unsubscribe { .. } | Request::Publish { .. } => {
+      Access::Authenticated
+    }
+    // events are only sent for tables the user can read
+    Request::KeyspaceSubscribe { .. } | Request::KeyspaceUnsubscribe { .. } => {
+      Access::Authenticated
+    }
+    Request::SlowLogGet { .. }
+    | Request::SlowLogReset
+    | Request::Monitor { .. }
+    | Request::ClientList
+    | Request::ClientKill { .. } => Access::Admin,
+    Request::InsertTable { table, .. } => Access::Table(Role::Admin, table),
+    Request::GetTable { table } => Access::Table(Role::Read, table),
+    Request::DeleteTable { table } => Access::Table(Role::Admin, table),
+    Request::List { table } => Access::Table(Role::Read, table),
+    Request::Get { table, .. } => Access::Table(Role::Read, table),
+    Request::Delete { table, .. } => Access::Table(Role::Write, table),
+    Request::Watch { table, .. } => Access::Table(Role::Read, table),
+    Request::Push { table, .. } => Access::Table(Role::Write, table),
+    Request::Pop { table, .. } => Access::Table(Role::Write, table),
+    Request::BlockingPop { table, .. } => Access::Table(Role::Write, table),
+    Request::Range { table, .. } => Access::Table(Role::Read, table),
+    Request::Trim { table, .. } => Access::Table(Role::Write, table),
+    Request::Length { table, .. } => Access::Table(Role::Read, table),
+    Request::Insert { table, .. } => Access::Table(Role::Write, table),
+    // `Request` is non-exhaustive, so anything not listed above is admin-only until it is
+    _ => Access::Admin,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::{from_value, json};
+
+  fn access_of(req: serde_json::Value) -> Access<'static> {
+    let req: &'static Request = Box::leak(Box::new(from_value(req).unwrap()));
+    access(req)
+  }
+
+  #[test]
+  fn access_without_table() {
+    assert_eq!(access_of(json!({"action": "PING"})), Access::Public);
+    let auth = json!({"action": "AUTH", "username": "u", "password": "p"});
+    assert_eq!(access_of(auth), Access::Public);
+    let compress = json!({"action": "COMPRESS STREAM", "mode": 0});
+    assert_eq!(access_of(compress), Access::Authenticated);
+
+    assert_eq!(access_of(json!({"action": "INFO"})), Access::Authenticated);
+    assert_eq!(
+      access_of(json!({"action": "LIST TABLE"})),
+      Access::Authenticated
+    );
+    let keyspace = json!({"action": "KEYSPACE SUBSCRIBE", "table": "*", "key": null});
+    assert_eq!(access_of(keyspace), Access::Authenticated);
+    let subscribe = json!({"action": "SUBSCRIBE", "channels": ["c"], "patterns": null});
+    assert_eq!(access_of(subscribe), Access::Authenticated);
+    let publish = json!({"action": "PUBLISH", "channel": "c", "data": 1});
+    assert_eq!(access_of(publish), Access::Authenticated);
+
+    assert_eq!(access_of(json!({"action": "SLOWLOG RESET"})), Access::Admin);
+    assert_eq!(access_of(json!({"action": "CLIENT LIST"})), Access::Admin);
+    let kill = json!({"action": "CLIENT KILL", "id": 1, "peer": null, "user": null});
+    assert_eq!(access_of(kill), Access::Admin);
+    let monitor = json!({"action": "MONITOR", "table": null, "actions": null});
+    assert_eq!(access_of(monitor), Access::Admin);
+  }
+
+  #[test]
+  fn access_with_table() {
+    let table = |role| Access::Table(role, "t");
+    let get = json!({"action": "GET", "table": "t", "key": "k"});
+    assert_eq!(access_of(get), table(Role::Read));
+    assert_eq!(
+      access_of(json!({"action": "LIST", "table": "t"})),
+      table(Role::Read)
+    );
+    let range = json!({"action": "RANGE", "table": "t", "key": "k", "start": 0, "stop": -1});
+    assert_eq!(access_of(range), table(Role::Read));
+    let watch = json!({"action": "WATCH", "table": "t", "key": "k", "timeout": null});
+    assert_eq!(access_of(watch), table(Role::Read));
+
+    let insert =
+      json!({"action": "INSERT", "table": "t", "key": "k", "value": 1, "lifetime": null});
+    assert_eq!(access_of(insert), table(Role::Write));
+    let delete = json!({"action": "DELETE", "table": "t", "key": "k"});
+    assert_eq!(access_of(delete), table(Role::Write));
+    let pop =
+      json!({"action": "BLOCKING POP", "table": "t", "key": "k", "end": "FRONT", "timeout": 1});
+    assert_eq!(access_of(pop), table(Role::Write));
+
+    let create = json!({"action": "INSERT TABLE", "table": "t", "contents": null});
+    assert_eq!(access_of(create), table(Role::Admin));
+    let drop = json!({"action": "DELETE TABLE", "table": "t"});
+    assert_eq!(access_of(drop), table(Role::Admin));
+  }
+
+  fn user(grants: &[(&str, Role)]) -> User {
+    let grants = grants.iter().map(|&(p, r)| (p.to_owned(), r)).collect();
+    let conf = Box::leak(Box::new(UserConfig {
+      password: String::new(),
+      grants,
+    }));
+    User { name: "test", conf }
+  }
+
+  #[test]
+  fn roles_take_the_highest_matching_grant() {
+    let user = user(&[
+      ("*", Role::Read),
+      ("app_*", Role::Write),
+      ("app_config", Role::Admin),
+    ]);
+    assert_eq!(user.role("other"), Some(Role::Read));
+    assert_eq!(user.role("app_users"), Some(Role::Write));
+    assert_eq!(user.role("app_config"), Some(Role::Admin));
+    assert!(user.can(Role::Read, "app_users"));
+    assert!(!user.can(Role::Admin, "app_users"));
+    assert!(!user.is_admin());
+  }
+
+  #[test]
+  fn roles_need_a_matching_grant() {
+    let user = user(&[("app_?", Role::Write)]);
+    assert_eq!(user.role("app_1"), Some(Role::Write));
+    assert_eq!(user.role("app_10"), None);
+    assert!(!user.can(Role::Read, "other"));
+    assert!(!self::user(&[]).can(Role::Read, "t"));
+    assert!(self::user(&[("*", Role::Admin)]).is_admin());
+  }
+
+  #[test]
+  fn plain_text_passwords_are_hashed_on_load() {
+    let mut conf = crate::config::Config {
+      username: Some("old".to_owned()),
+      password: Some("secret".to_owned()),
+      ..Default::default()
+    };
+    conf.load_users();
+    let stored = &conf.users["old"].password;
+    assert!(is_hashed(stored));
+    assert!(verify_password(stored, "secret"));
+    assert!(!verify_password(stored, "wrong"));
+    assert!(!verify_password("secret", "secret"));
+  }
+}
@@ -0,0 +1,94 @@
+use crate::compression::{read, write, Level, Mode};
+use crate::{logger::*, wrap_fatal, SNAPSHOT_MAGIC};
+
+use bytes::Bytes;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// each codec & level is run this many times, and the fastest run is reported
+const ROUNDS: usize = 3;
+
+#[inline]
+fn throughput(len: usize, time: Duration) -> f64 {
+  len as f64 / 1024.0 / 1024.0 / time.as_secs_f64()
+}
+
+// COMPRESSION BENCHMARK
+
+pub async fn compression(path: &Path) {
+  info!("Loading sample from {}...", path.to_string_lossy());
+  let mut data = wrap_fatal!(std::fs::read(path), "Failed to read sample: {}");
+
+  // benchmark the uncompressed snapshot, not the compressed one
+  if data.starts_with(SNAPSHOT_MAGIC) && data.len() > SNAPSHOT_MAGIC.len() {
+    let mode = data[SNAPSHOT_MAGIC.len()];
+    let mode = wrap_fatal!(
+      Mode::try_from(mode),
+      "Invalid database compression mode: {}"
+    );
+    let src = &data[SNAPSHOT_MAGIC.len() + 1..];
+    let mut reader = wrap_fatal!(
+      read::reader_sync(src, mode),
+      "Failed to decompress sample: {}"
+    );
+    let mut raw = Vec::new();
+    wrap_fatal!(
+      reader.read_to_end(&mut raw),
+      "Failed to decompress sample: {}"
+    );
+    drop(reader);
+    data = raw;
+  }
+
+  let len = data.len();
+  let data = Bytes::from(data);
+  info!("Benchmarking {} bytes, best of {} rounds...", len, ROUNDS);
+
+  println!(
+    "{:<8} {:<8} {:>8} {:>16} {:>16}",
+    "CODEC", "LEVEL", "RATIO", "COMPRESS", "DECOMPRESS"
+  );
+
+  for mode in Mode::ALL {
+    let levels = [Level::Default].iter().chain(mode.bench_levels());
+
+    for &level in levels {
+      let mut compressed = Bytes::new();
+      let mut comp_time = Duration::MAX;
+      let mut decomp_time = Duration::MAX;
+
+      for _ in 0..ROUNDS {
+        let start = Instant::now();
+        compressed = wrap_fatal!(
+          write::bytes_to_bytes(data.clone(), mode, level).await,
+          "Compression failed: {}"
+        );
+        comp_time = comp_time.min(start.elapsed());
+
+        let start = Instant::now();
+        let out = wrap_fatal!(
+          read::bytes_to_bytes(compressed.clone(), len, mode).await,
+          "Decompression failed: {}"
+        );
+        decomp_time = decomp_time.min(start.elapsed());
+
+        if out != data {
+          fatal!("{:?} did not round-trip the sample", mode);
+        }
+      }
+
+      println!(
+        "{:<8} {:<8} {:>7.2}x {:>11.1} MB/s {:>11.1} MB/s",
+        format!("{mode:?}"),
+        match level {
+          Level::Default => "default".to_owned(),
+          Level::Precise(level) => level.to_string(),
+        },
+        len as f64 / compressed.len().max(1) as f64,
+        throughput(len, comp_time),
+        throughput(len, decomp_time),
+      );
+    }
+  }
+}
@@ -0,0 +1,161 @@
+use crate::auth::User;
+use crate::config::ConnectionConfig;
+use crate::connection::{Peer, Traffic};
+use protocol::ClientInfo;
+
+use scc::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, LazyLock};
+use std::time::SystemTime;
+use tokio::sync::Notify;
+
+// REGISTRY
+
+struct Client {
+  info: ClientInfo,
+  kill: Arc<Notify>,
+}
+
+static CLIENTS: LazyLock<HashMap<u64, Client>> = LazyLock::new(HashMap::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Every open connection, oldest first.
+pub async fn list() -> Vec<ClientInfo> {
+  let mut clients = Vec::with_capacity(CLIENTS.len());
+  CLIENTS
+    .scan_async(|_, c| clients.push(c.info.clone()))
+    .await;
+  clients.sort_unstable_by_key(|c| c.id);
+  clients
+}
+
+// `peer` exactly as listed, or an IP matching TCP peers on any port
+fn peer_matches(filter: &str, peer: &str) -> bool {
+  if filter == peer {
+    return true;
+  }
+  let ip = filter.trim_start_matches('[').trim_end_matches(']');
+  match (ip.parse::<IpAddr>(), peer.parse::<SocketAddr>()) {
+    (Ok(ip), Ok(addr)) => ip == addr.ip(),
+    _ => false,
+  }
+}
+
+/// Close every connection matching all of the given fields, returning how many matched.
+pub async fn kill(id: Option<u64>, peer: Option<&str>, user: Option<&str>) -> u64 {
+  let mut killed = 0;
+  CLIENTS
+    .scan_async(|_, c| {
+      let info = &c.info;
+      if id.is_none_or(|id| id == info.id)
+        && peer.is_none_or(|p| peer_matches(p, &info.peer))
+        && user.is_none_or(|u| Some(u) == info.user.as_deref())
+      {
+        // stored if the connection is busy, so it's seen on its next request
+        c.kill.notify_one();
+        killed += 1;
+      }
+    })
+    .await;
+  killed
+}
+
+/// Close the connection with `id`, if it's still open.
+pub fn kill_id(id: u64) {
+  CLIENTS.read(&id, |_, c| c.kill.notify_one());
+}
+
+// REGISTRATION
+
+/// A connection's entry in the registry, removed when dropped.
+pub struct Registration {
+  id: u64,
+  kill: Arc<Notify>,
+}
+
+impl Registration {
+  pub fn new(peer: Peer, user: Option<User>, listener: &ConnectionConfig) -> Self {
+    let id = NEXT_ID.fetch_add(1, Relaxed);
+    let kill = Arc::new(Notify::new());
+    let info = ClientInfo {
+      id,
+      peer: peer.to_string(),
+      user: user.map(|u| u.name.to_owned()),
+      listener: listener.addr(),
+      tls: listener.tls,
+      since: Some(SystemTime::now()),
+      last_command: None,
+      bytes_in: 0,
+      bytes_out: 0,
+      buffer_memory: 0,
+    };
+    let _ = CLIENTS.insert(
+      id,
+      Client {
+        info,
+        kill: kill.clone(),
+      },
+    );
+    Self { id, kill }
+  }
+
+  #[inline]
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Record a handled request.
+  pub fn update(&self, action: &'static str, user: Option<User>, traffic: Traffic, memory: usize) {
+    CLIENTS.update(&self.id, |_, c| {
+      let info = &mut c.info;
+      if info.last_command.as_deref() != Some(action) {
+        info.last_command = Some(action.to_owned());
+      }
+      if info.user.as_deref() != user.map(|u| u.name) {
+        info.user = user.map(|u| u.name.to_owned());
+      }
+      info.bytes_in = traffic.bytes_in;
+      info.bytes_out = traffic.bytes_out;
+      info.buffer_memory = memory as u64;
+    });
+  }
+
+  /// Wait until the connection is killed. Cancel safe.
+  #[inline]
+  pub async fn killed(&self) {
+    self.kill.notified().await
+  }
+}
+
+impl Drop for Registration {
+  fn drop(&mut self) {
+    CLIENTS.remove(&self.id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn peers_match_exactly() {
+    assert!(peer_matches("127.0.0.1:5000", "127.0.0.1:5000"));
+    assert!(!peer_matches("127.0.0.1:5001", "127.0.0.1:5000"));
+    assert!(peer_matches("[::1]:5000", "[::1]:5000"));
+    assert!(peer_matches("unix:1000", "unix:1000"));
+    assert!(!peer_matches("unix:1000", "unix:0"));
+    assert!(!peer_matches("unix", "unix:1000"));
+  }
+
+  #[test]
+  fn peers_match_by_ip_without_a_port() {
+    assert!(peer_matches("127.0.0.1", "127.0.0.1:5000"));
+    assert!(!peer_matches("127.0.0.2", "127.0.0.1:5000"));
+    assert!(peer_matches("::1", "[::1]:5000"));
+    assert!(peer_matches("[::1]", "[::1]:5000"));
+    assert!(peer_matches("2001:db8::1", "[2001:db8:0::1]:80"));
+    assert!(!peer_matches("::1", "127.0.0.1:5000"));
+    assert!(!peer_matches("127.0.0.1", "unix:1000"));
+  }
+}
@@ -2,10 +2,12 @@ pub mod read;
 pub mod write;
 
 use crate::SyncHashSet;
+use serde::{Deserialize, Serialize};
 
 // COMPRESSION MODE
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 #[rustfmt::skip]
 pub enum Mode {
@@ -22,6 +24,29 @@ pub enum Mode {
 use Mode::*;
 
 impl Mode {
+  pub const ALL: [Self; 8] = [Lz4, Zstd, Snappy, Brotli, Deflate, Zlib, Gzip, Lzw];
+
+  /// Levels worth benchmarking besides the default, none of which repeats its setting.
+  #[rustfmt::skip]
+  pub fn bench_levels(self) -> &'static [Level] {
+    use Level::Precise;
+    match self {
+      // zstd's default is 3
+      Zstd => &[Precise(1), Precise(9), Precise(19)],
+      // brotli's default is its best quality, 11
+      Brotli => &[Precise(1), Precise(6)],
+      // flate codecs default to their fastest level, 1
+      Deflate | Zlib | Gzip => &[Precise(6), Precise(9)],
+      Lz4 | Snappy | Lzw => &[],
+    }
+  }
+
+  /// Whether this mode has async streaming encoders/decoders.
+  #[inline]
+  pub fn streamable(self) -> bool {
+    !matches!(self, Lz4 | Lzw)
+  }
+
   #[inline]
   #[rustfmt::skip]
   pub fn tcpset(value: u8) -> SyncHashSet<Self> {
@@ -38,6 +63,17 @@ impl Mode {
   }
 }
 
+// COMPRESSION LEVEL
+
+/// Compression level. Ignored by modes without levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Level {
+  #[default]
+  Default,
+  /// Codec-specific level, clamped to the range the codec supports.
+  Precise(u32),
+}
+
 impl TryFrom<u8> for Mode {
   type Error = String;
   #[inline]
@@ -55,3 +91,139 @@ impl TryFrom<u8> for Mode {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bytes::Bytes;
+  use std::io::{ErrorKind, Read, Write};
+
+  // compressible but not trivially so, and larger than the LZW stream buffers
+  fn sample(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+      .map(|i| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        match i % 7 {
+          0 => (state >> 24) as u8,
+          _ => b"void cache "[i % 11],
+        }
+      })
+      .collect()
+  }
+
+  fn stream_write(src: &[u8], mode: Mode, level: Level, chunk: usize) -> Vec<u8> {
+    let mut writer = write::StreamWriter::new(Vec::new(), mode, level).unwrap();
+    for part in src.chunks(chunk) {
+      writer.write_all(part).unwrap();
+    }
+    writer.finish().unwrap()
+  }
+
+  fn stream_read(src: &[u8], mode: Mode) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    read::reader_sync(src, mode)
+      .unwrap()
+      .read_to_end(&mut out)?;
+    Ok(out)
+  }
+
+  #[test]
+  fn lzw_stream_round_trip() {
+    for len in [0, 1, 4096, 300_000] {
+      let src = sample(len);
+      for chunk in [1, 1000, 1 << 20] {
+        let compressed = stream_write(&src, Lzw, Level::Default, chunk);
+        assert_eq!(
+          stream_read(&compressed, Lzw).unwrap(),
+          src,
+          "len {len}, chunk {chunk}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn lzw_stream_reader_rejects_truncated_input() {
+    let compressed = stream_write(&sample(100_000), Lzw, Level::Default, 4096);
+    let truncated = &compressed[..compressed.len() / 2];
+    let err = stream_read(truncated, Lzw).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn snapshot_streams_round_trip() {
+    let src = sample(200_000);
+    for mode in Mode::ALL {
+      for level in [Level::Default, Level::Precise(1)] {
+        let compressed = stream_write(&src, mode, level, 8192);
+        let out = stream_read(&compressed, mode).unwrap();
+        assert!(out == src, "{mode:?} at {level:?}");
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn bytes_round_trip() {
+    let src = sample(200_000);
+    for mode in Mode::ALL {
+      for level in [Level::Default, Level::Precise(1)] {
+        let compressed = write::bytes_to_bytes(Bytes::from(src.clone()), mode, level);
+        let compressed = compressed.await.unwrap();
+        let out = read::bytes_to_bytes(compressed, src.len(), mode)
+          .await
+          .unwrap();
+        assert!(out == src, "{mode:?} at {level:?}");
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn lzw_one_shot_and_stream_formats_match() {
+    let src = sample(100_000);
+    let one_shot = write::bytes_to_bytes(Bytes::from(src.clone()), Lzw, Level::Default);
+    let one_shot = one_shot.await.unwrap();
+    assert_eq!(stream_read(&one_shot, Lzw).unwrap(), src);
+
+    let streamed = Bytes::from(stream_write(&src, Lzw, Level::Default, 4096));
+    let out = read::bytes_to_bytes(streamed, src.len(), Lzw)
+      .await
+      .unwrap();
+    assert!(out == src);
+  }
+
+  #[tokio::test]
+  async fn default_level_keeps_codec_defaults() {
+    let src = sample(100_000);
+    let compress = |mode| write::bytes_to_bytes(Bytes::from(src.clone()), mode, Level::Default);
+
+    let mut deflate = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    deflate.write_all(&src).unwrap();
+    assert!(compress(Deflate).await.unwrap() == deflate.finish().unwrap());
+
+    let mut brotli = Vec::new();
+    let params = brotli::enc::BrotliEncoderParams::default();
+    brotli::BrotliCompress(&mut src.as_slice(), &mut brotli, &params).unwrap();
+    assert!(compress(Brotli).await.unwrap() == brotli);
+
+    let zstd = zstd::bulk::compress(&src, 0).unwrap();
+    assert!(compress(Zstd).await.unwrap() == zstd);
+  }
+
+  #[tokio::test]
+  async fn bench_levels_differ() {
+    let src = sample(200_000);
+    for mode in Mode::ALL {
+      let compress = |level| write::bytes_to_bytes(Bytes::from(src.clone()), mode, level);
+      let mut outs = vec![compress(Level::Default).await.unwrap()];
+      for &level in mode.bench_levels() {
+        let out = compress(level).await.unwrap();
+        assert!(
+          !outs.contains(&out),
+          "{mode:?} at {level:?} repeats another level"
+        );
+        outs.push(out);
+      }
+    }
+  }
+}
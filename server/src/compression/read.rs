@@ -8,8 +8,9 @@ use flate2::bufread::GzDecoder as GzipReaderSync;
 use flate2::bufread::ZlibDecoder as ZlibReaderSync;
 use lz4_flex::frame::FrameDecoder as Lz4Reader;
 use snap::read::FrameDecoder as SnappyReaderSync;
-use weezl::{decode::Decoder as LzwReader, BitOrder::Msb};
+use weezl::{decode::Decoder as LzwReader, BitOrder::Msb, LzwStatus};
 use zstd::bulk::Decompressor as ZstdReaderSync;
+use zstd::stream::read::Decoder as ZstdStreamReaderSync;
 // ASYNC READERS
 use async_compression::tokio::bufread::BrotliDecoder as BrotliReaderAsync;
 use async_compression::tokio::bufread::DeflateDecoder as DeflateReaderAsync;
@@ -19,12 +20,10 @@ use async_compression::tokio::bufread::ZstdDecoder as ZstdReaderAsync;
 use tokio_snappy::SnappyIO as SnappyAsync;
 
 use bytes::{Bytes, BytesMut};
-use std::io::{Cursor, Read};
-use std::mem::transmute;
-use tokio::io::{AsyncBufRead, AsyncReadExt};
+use std::io::{BufRead, Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult};
+use std::pin::Pin;
+use tokio::io::{AsyncBufRead, AsyncRead};
 use tokio::task::spawn_blocking;
-use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
-use tokio_util::io::SyncIoBridge;
 
 // BYTES -> BYTES
 
@@ -45,45 +44,71 @@ fn bytes_to_bytes_sync(src: Bytes, len: usize, mode: Mode) -> Result<Bytes> {
     Deflate => DeflateReaderSync::new(&*src).read_exact(&mut out)?,
     Zlib => ZlibReaderSync::new(&*src).read_exact(&mut out)?,
     Gzip => GzipReaderSync::new(&*src).read_exact(&mut out)?,
-    Lzw => _ = LzwReader::new(Msb, 9).decode_bytes(&src, &mut out).status?,
+    Lzw => LzwStreamReader(&*src, LzwReader::new(Msb, 9)).read_exact(&mut out)?,
   }
 
   Ok(out.freeze())
 }
 
-// READER -> BYTES
+// READER -> READER (SYNC)
 
-pub async fn read_to_bytes<R>(src: &mut R, full: usize, mode: Mode) -> Result<Bytes>
-where
-  R: AsyncBufRead + Send + Sync + Unpin + 'static,
-{
-  let mut out = BytesMut::zeroed(full);
+/// Wrap a blocking reader in a streaming decompressor.
+pub fn reader_sync<'a, R: BufRead + 'a>(src: R, mode: Mode) -> Result<Box<dyn Read + 'a>> {
+  Ok(match mode {
+    Lz4 => Box::new(Lz4Reader::new(src)),
+    Zstd => Box::new(ZstdStreamReaderSync::with_buffer(src)?),
+    Snappy => Box::new(SnappyReaderSync::new(src)),
+    Brotli => Box::new(BrotliReaderSync::new(src, 4096)),
+    Deflate => Box::new(DeflateReaderSync::new(src)),
+    Zlib => Box::new(ZlibReaderSync::new(src)),
+    Gzip => Box::new(GzipReaderSync::new(src)),
+    Lzw => Box::new(LzwStreamReader(src, LzwReader::new(Msb, 9))),
+  })
+}
 
-  match mode {
-    Lz4 => {
-      // SAFETY: R has static lifetime bound
-      let src = unsafe { transmute::<&mut R, &'static mut R>(src) };
-      let src = SyncIoBridge::new(src);
-      // Return here because out gets moved.
-      return spawn_blocking(move || -> Result<Bytes> {
-        Lz4Reader::new(src).read_exact(&mut out)?;
-        Ok(out.freeze())
-      })
-      .await?;
+// weezl only offers reader -> writer streaming, so drive the decoder by hand
+struct LzwStreamReader<R: BufRead>(R, LzwReader);
+
+impl<R: BufRead> Read for LzwStreamReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+    if buf.is_empty() || self.1.has_ended() {
+      return Ok(0);
     }
-    Zstd => _ = ZstdReaderAsync::new(src).read_exact(&mut out).await?,
-    Snappy => _ = SnappyAsync::new(src).read_exact(&mut out).await?,
-    Brotli => _ = BrotliReaderAsync::new(src).read_exact(&mut out).await?,
-    Deflate => _ = DeflateReaderAsync::new(src).read_exact(&mut out).await?,
-    Zlib => _ = ZlibReaderAsync::new(src).read_exact(&mut out).await?,
-    Gzip => _ = GzipReaderAsync::new(src).read_exact(&mut out).await?,
-    Lzw => {
-      // Cost of this should be less than converting to and from a Vec<u8>.
-      let out = Cursor::new(out.as_mut()).compat_write();
-      let mut lzw = LzwReader::new(Msb, 9);
-      lzw.into_async(out).decode(src.compat()).await.status?;
+    loop {
+      let src = self.0.fill_buf()?;
+      let eof = src.is_empty();
+      let res = self.1.decode_bytes(src, buf);
+      self.0.consume(res.consumed_in);
+      match res
+        .status
+        .map_err(|e| IoError::new(IoErrorKind::InvalidData, e))?
+      {
+        _ if res.consumed_out > 0 => return Ok(res.consumed_out),
+        LzwStatus::Done => return Ok(0),
+        _ if eof => return Err(IoErrorKind::UnexpectedEof.into()),
+        _ => continue,
+      }
     }
   }
+}
 
-  Ok(out.freeze())
+// READER -> READER (ASYNC)
+
+pub type AsyncReader = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+/// Wrap an async reader in a streaming decompressor.
+/// LZ4 and LZW have no async streaming decoders, so they are rejected.
+pub fn reader_async<R>(src: R, mode: Mode) -> Result<AsyncReader>
+where
+  R: AsyncBufRead + Send + Sync + Unpin + 'static,
+{
+  Ok(match mode {
+    Zstd => Box::pin(ZstdReaderAsync::new(src)),
+    Snappy => Box::pin(SnappyAsync::new(src)),
+    Brotli => Box::pin(BrotliReaderAsync::new(src)),
+    Deflate => Box::pin(DeflateReaderAsync::new(src)),
+    Zlib => Box::pin(ZlibReaderAsync::new(src)),
+    Gzip => Box::pin(GzipReaderAsync::new(src)),
+    Lz4 | Lzw => return Err(format!("{mode:?} does not support stream compression").into()),
+  })
 }
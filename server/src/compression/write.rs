@@ -9,8 +9,9 @@ use flate2::write::ZlibEncoder as ZlibWriterSync;
 use flate2::Compression;
 use lz4_flex::frame::FrameEncoder as Lz4Writer;
 use snap::write::FrameEncoder as SnappyWriterSync;
-use weezl::{encode::Encoder as LzwWriter, BitOrder::Msb};
+use weezl::{encode::Encoder as LzwWriter, BitOrder::Msb, LzwStatus};
 use zstd::bulk::Compressor as ZstdWriterSync;
+use zstd::stream::write::Encoder as ZstdStreamWriterSync;
 // ASYNC WRITERS
 use async_compression::tokio::write::BrotliEncoder as BrotliWriterAsync;
 use async_compression::tokio::write::DeflateEncoder as DeflateWriterAsync;
@@ -20,8 +21,9 @@ use async_compression::tokio::write::ZstdEncoder as ZstdWriterAsync;
 use tokio_snappy::SnappyIO as SnappyAsync;
 
 use bytes::Bytes;
-use std::io::Write;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult, Write};
 use std::mem::transmute;
+use std::pin::Pin;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::task::spawn_blocking;
 use tokio_util::compat::TokioAsyncWriteCompatExt;
@@ -29,18 +31,45 @@ use tokio_util::io::SyncIoBridge;
 
 // BYTES -> BYTES
 
-const FLATE_FAST: Compression = Compression::fast();
+// the defaults are what snapshots and connections have always used
+
+fn zstd_level(level: Level) -> i32 {
+  match level {
+    Level::Default => 0,
+    Level::Precise(level) => level.min(22) as i32,
+  }
+}
+
+fn brotli_params(level: Level) -> BrotliEncoderParams {
+  match level {
+    Level::Default => BrotliEncoderParams::default(),
+    Level::Precise(quality) => BrotliEncoderParams {
+      quality: quality.min(11) as i32,
+      ..Default::default()
+    },
+  }
+}
+
+fn flate_level(level: Level) -> Compression {
+  match level {
+    Level::Default => Compression::fast(),
+    Level::Precise(level) => Compression::new(level.min(9)),
+  }
+}
 
 #[inline]
-pub async fn bytes_to_bytes(src: Bytes, mode: Mode) -> Result<Bytes> {
+pub async fn bytes_to_bytes(src: Bytes, mode: Mode, level: Level) -> Result<Bytes> {
   // Since we already have the full byte array, avoid extraneous async conversion operations.
-  spawn_blocking(move || bytes_to_bytes_sync(&src, mode)).await?
+  spawn_blocking(move || bytes_to_bytes_sync(&src, mode, level)).await?
 }
 
-fn bytes_to_bytes_sync(src: &[u8], mode: Mode) -> Result<Bytes> {
+fn bytes_to_bytes_sync(src: &[u8], mode: Mode, level: Level) -> Result<Bytes> {
   // special paths; avoids allocating our own vec
   match mode {
-    Zstd => return Ok(ZstdWriterSync::new(0)?.compress(src)?.into()),
+    Zstd => {
+      let out = ZstdWriterSync::new(zstd_level(level))?.compress(src)?;
+      return Ok(out.into());
+    }
     Lzw => return Ok(LzwWriter::new(Msb, 9).encode(src)?.into()),
     _ => {}
   }
@@ -49,15 +78,19 @@ fn bytes_to_bytes_sync(src: &[u8], mode: Mode) -> Result<Bytes> {
   let mut out = Vec::with_capacity(src.len() / 2);
 
   match mode {
-    Lz4 => Lz4Writer::new(&mut out).write_all(src)?,
+    Lz4 => {
+      let mut lz4 = Lz4Writer::new(&mut out);
+      lz4.write_all(src)?;
+      lz4.finish()?;
+    }
     Snappy => SnappyWriterSync::new(&mut out).write_all(src)?,
     Brotli => {
-      let params = BrotliEncoderParams::default();
+      let params = brotli_params(level);
       BrotliWriterSync::with_params(&mut out, 0, &params).write_all(src)?
     }
-    Deflate => DeflateWriterSync::new(&mut out, FLATE_FAST).write_all(src)?,
-    Zlib => ZlibWriterSync::new(&mut out, FLATE_FAST).write_all(src)?,
-    Gzip => GzipWriterSync::new(&mut out, FLATE_FAST).write_all(src)?,
+    Deflate => DeflateWriterSync::new(&mut out, flate_level(level)).write_all(src)?,
+    Zlib => ZlibWriterSync::new(&mut out, flate_level(level)).write_all(src)?,
+    Gzip => GzipWriterSync::new(&mut out, flate_level(level)).write_all(src)?,
     _ => unreachable!(),
   }
 
@@ -92,3 +125,156 @@ where
 
   Ok(())
 }
+
+// WRITER -> WRITER (ASYNC)
+
+pub type AsyncWriter = Pin<Box<dyn AsyncWrite + Send + Sync>>;
+
+/// Wrap an async writer in a streaming compressor.
+/// LZ4 and LZW have no async streaming encoders, so they are rejected.
+pub fn writer_async<W>(dst: W, mode: Mode) -> Result<AsyncWriter>
+where
+  W: AsyncWrite + Send + Sync + Unpin + 'static,
+{
+  Ok(match mode {
+    Zstd => Box::pin(ZstdWriterAsync::new(dst)),
+    Snappy => Box::pin(SnappyAsync::new(dst)),
+    Brotli => Box::pin(BrotliWriterAsync::new(dst)),
+    Deflate => Box::pin(DeflateWriterAsync::new(dst)),
+    Zlib => Box::pin(ZlibWriterAsync::new(dst)),
+    Gzip => Box::pin(GzipWriterAsync::new(dst)),
+    Lz4 | Lzw => return Err(format!("{mode:?} does not support stream compression").into()),
+  })
+}
+
+// WRITER -> WRITER (SYNC)
+
+/// Streaming compressor over a blocking writer.
+/// Must be `finish`ed, otherwise trailing frames may be silently dropped.
+pub enum StreamWriter<W: Write> {
+  Lz4(Lz4Writer<W>),
+  Zstd(ZstdStreamWriterSync<'static, W>),
+  Snappy(Box<SnappyWriterSync<W>>),
+  Brotli(Box<BrotliWriterSync<W>>),
+  Deflate(DeflateWriterSync<W>),
+  Zlib(ZlibWriterSync<W>),
+  Gzip(GzipWriterSync<W>),
+  Lzw(LzwStreamWriter<W>),
+}
+
+impl<W: Write> StreamWriter<W> {
+  pub fn new(dst: W, mode: Mode, level: Level) -> Result<Self> {
+    Ok(match mode {
+      Lz4 => Self::Lz4(Lz4Writer::new(dst)),
+      Zstd => Self::Zstd(ZstdStreamWriterSync::new(dst, zstd_level(level))?),
+      Snappy => Self::Snappy(Box::new(SnappyWriterSync::new(dst))),
+      Brotli => {
+        let params = brotli_params(level);
+        Self::Brotli(Box::new(BrotliWriterSync::with_params(dst, 4096, &params)))
+      }
+      Deflate => Self::Deflate(DeflateWriterSync::new(dst, flate_level(level))),
+      Zlib => Self::Zlib(ZlibWriterSync::new(dst, flate_level(level))),
+      Gzip => Self::Gzip(GzipWriterSync::new(dst, flate_level(level))),
+      Lzw => Self::Lzw(LzwStreamWriter::new(dst)),
+    })
+  }
+
+  /// Write any trailing data and return the inner writer.
+  pub fn finish(self) -> Result<W> {
+    Ok(match self {
+      Self::Lz4(w) => w.finish()?,
+      Self::Zstd(w) => w.finish()?,
+      Self::Snappy(w) => w.into_inner().map_err(|e| e.error().to_string())?,
+      Self::Brotli(w) => w.into_inner(),
+      Self::Deflate(w) => w.finish()?,
+      Self::Zlib(w) => w.finish()?,
+      Self::Gzip(w) => w.finish()?,
+      Self::Lzw(w) => w.finish()?,
+    })
+  }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+  #[inline]
+  fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+    match self {
+      Self::Lz4(w) => w.write(buf),
+      Self::Zstd(w) => w.write(buf),
+      Self::Snappy(w) => w.write(buf),
+      Self::Brotli(w) => w.write(buf),
+      Self::Deflate(w) => w.write(buf),
+      Self::Zlib(w) => w.write(buf),
+      Self::Gzip(w) => w.write(buf),
+      Self::Lzw(w) => w.write(buf),
+    }
+  }
+
+  #[inline]
+  fn flush(&mut self) -> IoResult<()> {
+    match self {
+      Self::Lz4(w) => w.flush(),
+      Self::Zstd(w) => w.flush(),
+      Self::Snappy(w) => w.flush(),
+      Self::Brotli(w) => w.flush(),
+      Self::Deflate(w) => w.flush(),
+      Self::Zlib(w) => w.flush(),
+      Self::Gzip(w) => w.flush(),
+      Self::Lzw(w) => w.flush(),
+    }
+  }
+}
+
+// weezl only offers reader -> writer streaming, so drive the encoder by hand
+pub struct LzwStreamWriter<W: Write> {
+  dst: W,
+  lzw: LzwWriter,
+  buf: Box<[u8]>,
+}
+
+impl<W: Write> LzwStreamWriter<W> {
+  fn new(dst: W) -> Self {
+    Self {
+      dst,
+      lzw: LzwWriter::new(Msb, 9),
+      buf: vec![0; 1 << 16].into_boxed_slice(),
+    }
+  }
+
+  fn finish(mut self) -> IoResult<W> {
+    self.lzw.finish();
+    loop {
+      let res = self.lzw.encode_bytes(&[], &mut self.buf);
+      self.dst.write_all(&self.buf[..res.consumed_out])?;
+      match res
+        .status
+        .map_err(|e| IoError::new(IoErrorKind::InvalidData, e))?
+      {
+        LzwStatus::Done => break,
+        LzwStatus::Ok => continue,
+        LzwStatus::NoProgress => return Err(IoErrorKind::WriteZero.into()),
+      }
+    }
+    self.dst.flush()?;
+    Ok(self.dst)
+  }
+}
+
+impl<W: Write> Write for LzwStreamWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+    let mut consumed = 0;
+    while consumed < buf.len() {
+      let res = self.lzw.encode_bytes(&buf[consumed..], &mut self.buf);
+      consumed += res.consumed_in;
+      res
+        .status
+        .map_err(|e| IoError::new(IoErrorKind::InvalidData, e))?;
+      self.dst.write_all(&self.buf[..res.consumed_out])?;
+    }
+    Ok(consumed)
+  }
+
+  #[inline]
+  fn flush(&mut self) -> IoResult<()> {
+    self.dst.flush()
+  }
+}
@@ -1,54 +1,543 @@
-use crate::Global;
-use serde::{Deserialize, Serialize};
+use crate::auth::{hash_password, is_hashed, DEFAULT_PASSWORD, DEFAULT_USERNAME};
+use crate::{compression::Mode, fatal, logger::*, wrap_fatal, Global, SyncHashMap};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ConnectionConfig {
+  #[serde(default = "enabled")]
   pub enabled: bool,
+  // generated configs listen on 127.0.0.1 only, use 0.0.0.0 or :: to accept remote connections
+  #[serde(default)]
   pub address: String,
+  #[serde(default)]
   pub port: u16,
+  // Unix socket path, used instead of address and port
+  pub path: Option<String>,
+  // Unix socket file permissions (e.g. 0o660), owner uid and group gid
+  pub mode: Option<u32>,
+  pub owner: Option<u32>,
+  pub group: Option<u32>,
+  // Unix socket peer uid -> username, authenticated via SO_PEERCRED
+  #[serde(default)]
+  pub peer_users: SyncHashMap<String, String>,
+  #[serde(default)]
   pub tls: bool,
+  // limit for this listener only, `max_conns` still applies to the total
+  pub max_conns: Option<usize>,
+  // connections are authenticated as this user without AUTH (trusted listeners only!)
+  pub user: Option<String>,
+  // drop TLS connections whose certificate doesn't map to a user
+  #[serde(default)]
+  pub require_client_cert: bool,
+}
+
+impl ConnectionConfig {
+  #[inline]
+  pub fn addr(&self) -> String {
+    if let Some(path) = &self.path {
+      return path.clone();
+    }
+    // bracket IPv6 addresses
+    match self.address.contains(':') {
+      true => format!("[{}]:{}", self.address, self.port),
+      false => format!("{}:{}", self.address, self.port),
+    }
+  }
+}
+
+#[inline(always)]
+fn enabled() -> bool {
+  true
+}
+
+#[inline(always)]
+fn burst() -> f64 {
+  1.0
+}
+
+#[inline(always)]
+fn recv_buffer_size() -> usize {
+  64 * 1024
+}
+
+#[inline(always)]
+fn buffer_pool_size() -> usize {
+  1024
+}
+
+#[inline(always)]
+fn expire_interval() -> u64 {
+  1
+}
+
+#[inline(always)]
+fn expire_batch() -> usize {
+  1000
+}
+
+#[inline(always)]
+fn save_compression() -> Option<Mode> {
+  Some(Mode::Zstd)
+}
+
+#[inline(always)]
+fn idle_timeout() -> u64 {
+  300
+}
+
+#[inline(always)]
+fn io_timeout() -> u64 {
+  30
+}
+
+#[inline(always)]
+fn audit_queue() -> usize {
+  4096
+}
+
+#[inline(always)]
+fn slowlog_threshold() -> u64 {
+  10_000
+}
+
+#[inline(always)]
+fn slowlog_size() -> usize {
+  128
+}
+
+// older configs have a single [conn] table instead of [[conn]]
+fn listeners<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<ConnectionConfig>, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum OneOrMany {
+    One(ConnectionConfig),
+    Many(Vec<ConnectionConfig>),
+  }
+
+  Ok(match OneOrMany::deserialize(de)? {
+    OneOrMany::One(conn) => vec![conn],
+    OneOrMany::Many(conns) => conns,
+  })
+}
+
+// a missing key means the default, so no compression is spelled "none"
+mod mode_or_none {
+  use super::*;
+  use serde::{de::IntoDeserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(mode: &Option<Mode>, ser: S) -> Result<S::Ok, S::Error> {
+    match mode {
+      Some(mode) => mode.serialize(ser),
+      None => ser.serialize_str("none"),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Mode>, D::Error> {
+    match String::deserialize(de)?.as_str() {
+      "none" => Ok(None),
+      mode => Mode::deserialize(IntoDeserializer::<D::Error>::into_deserializer(mode)).map(Some),
+    }
+  }
+}
+
+/// Which part of a client certificate names the user.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientIdentity {
+  #[default]
+  CommonName,
+  San,
 }
 
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub struct TlsConfig {
+  // PEM certificate chain, leaf first
   pub cert: String,
+  // PEM private key (PKCS#8, or PKCS#1/SEC1 with rustls)
   pub key: String,
+  #[serde(default)]
+  pub tls13_only: bool,
+  // ALPN protocols to advertise, in order of preference
+  #[serde(default)]
+  pub alpn: Vec<String>,
+  // PEM CA bundle; if set, clients are authenticated by their certificates (rustls only)
+  pub client_ca: Option<String>,
+  // allow clients without a certificate, who must AUTH instead
+  #[serde(default)]
+  pub client_cert_optional: bool,
+  #[serde(default)]
+  pub client_identity: ClientIdentity,
+  // certificate identity -> username; certificates without a mapped identity don't authenticate
+  #[serde(default)]
+  pub client_users: SyncHashMap<String, String>,
+  // seconds between checks of the files above for changes; SIGHUP always reloads
+  pub watch_interval: Option<u64>,
+}
+
+/// Token bucket rate limit, unset rates are unlimited.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RateLimit {
+  pub requests_per_sec: Option<f64>,
+  pub bytes_per_sec: Option<f64>,
+  // seconds of traffic at the full rate that can be used at once
+  #[serde(default = "burst")]
+  pub burst: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RateLimitConfig {
+  pub connection: Option<RateLimit>,
+  // shared by all connections from a source IP (not Unix sockets)
+  pub ip: Option<RateLimit>,
+  // shared by all connections authenticated as a user
+  pub user: Option<RateLimit>,
+}
+
+/// Brute-force protection for AUTH.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct AuthLimitConfig {
+  // failed AUTHs before a connection is closed
+  pub max_failures: Option<u32>,
+  // delay before answering a failed AUTH in milliseconds, doubled for each failure on a connection
+  pub delay: u64,
+  pub max_delay: u64,
+  // failures from one IP within `window` seconds before it is locked out for `lockout` seconds
+  pub ip_max_failures: Option<u32>,
+  pub window: u64,
+  pub lockout: u64,
+}
+
+impl Default for AuthLimitConfig {
+  fn default() -> Self {
+    Self {
+      max_failures: Some(5),
+      delay: 250,
+      max_delay: 5000,
+      ip_max_failures: Some(20),
+      window: 300,
+      lockout: 900,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AuditConfig {
+  // JSON lines, appended to
+  pub path: String,
+  // include inserted values, otherwise they're left out
+  #[serde(default)]
+  pub values: bool,
+  // events waiting to be written before requests wait for the disk
+  #[serde(default = "audit_queue")]
+  pub queue: usize,
+}
+
+/// What happens when a message is published to a subscriber whose queue is full.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowConsumer {
+  // the message is dropped for that subscriber only
+  #[default]
+  Drop,
+  // the subscriber is disconnected
+  Disconnect,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct PubSubConfig {
+  // messages queued per subscribed connection
+  pub queue_size: usize,
+  pub slow_consumer: SlowConsumer,
+  // channels, patterns and keyspace subscriptions per connection
+  pub max_subscriptions: usize,
+}
+
+impl Default for PubSubConfig {
+  fn default() -> Self {
+    Self {
+      queue_size: 1024,
+      slow_consumer: SlowConsumer::Drop,
+      max_subscriptions: 1024,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+  #[default]
+  Text,
+  // one JSON object per line, including the connection and request spans
+  Json,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+  // a level or filter directives, e.g. `info,void::connection=trace`
+  // RUST_LOG takes precedence if set
+  pub level: String,
+  pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    Self {
+      level: "info".to_owned(),
+      format: LogFormat::Text,
+    }
+  }
+}
+
+/// Ordered so that each role implies the ones before it.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+  Read,
+  Write,
+  Admin,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct UserConfig {
+  // Argon2 PHC string from `void hash-password` (plain text is deprecated, and hashed on load)
+  pub password: String,
+  // table name pattern (supports `*` and `?`) -> role
+  #[serde(default)]
+  pub grants: SyncHashMap<String, Role>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct UsersFile {
+  pub users: SyncHashMap<String, UserConfig>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
-  pub conn: ConnectionConfig,
+  #[serde(deserialize_with = "listeners")]
+  pub conn: Vec<ConnectionConfig>,
   pub tls: Option<TlsConfig>,
   pub autosave_interval: u64,
-  pub username: String,
-  pub password: String,
+  // seconds between sweeps for expired keys that haven't been accessed
+  #[serde(default = "expire_interval")]
+  pub expire_interval: u64,
+  // most expired keys removed per sweep, the rest are left for the next one
+  #[serde(default = "expire_batch")]
+  pub expire_batch: usize,
+  // also sweep while nothing listens for expiry, to free keys that are never accessed again
+  #[serde(default)]
+  pub expire_sweep: bool,
+  // "none" saves the database as raw MessagePack
+  #[serde(default = "save_compression", with = "mode_or_none")]
+  pub save_compression: Option<Mode>,
+  #[serde(default)]
+  pub users: SyncHashMap<String, UserConfig>,
+  // extra users loaded from a separate TOML file, in the same format as `users`
+  pub users_file: Option<String>,
+  // deprecated: single admin user, merged into `users` on load
+  pub username: Option<String>,
+  pub password: Option<String>,
+  // allow the default credentials on non-loopback addresses
+  #[serde(default)]
+  pub allow_default_credentials: bool,
+  // refuse to start if any user password is stored as plain text
+  #[serde(default)]
+  pub strict_passwords: bool,
   pub max_conns: usize,
   pub max_message_size: usize,
   pub compress_threshold: usize,
+  // receive buffers grown past this are freed after use instead of kept or pooled
+  #[serde(default = "recv_buffer_size")]
+  pub recv_buffer_size: usize,
+  // idle receive buffers kept for reuse by new connections
+  #[serde(default = "buffer_pool_size")]
+  pub buffer_pool_size: usize,
+  // timeouts in seconds, connections are closed when exceeded, 0 disables one
+  // idle: waiting for the next request
+  // read: receiving the rest of a request (and the TLS handshake)
+  // write: sending a response
+  #[serde(default = "idle_timeout")]
+  pub idle_timeout: u64,
+  #[serde(default = "io_timeout")]
+  pub read_timeout: u64,
+  #[serde(default = "io_timeout")]
+  pub write_timeout: u64,
+  #[serde(default)]
+  pub rate_limit: RateLimitConfig,
+  #[serde(default)]
+  pub auth_limit: AuthLimitConfig,
+  // audit log of AUTH and mutating requests
+  pub audit: Option<AuditConfig>,
+  // address:port to serve Prometheus metrics on over HTTP
+  pub metrics: Option<String>,
+  // requests taking at least this many microseconds are kept in the slow log
+  #[serde(default = "slowlog_threshold")]
+  pub slowlog_threshold: u64,
+  // slow requests kept, oldest dropped first, 0 disables the slow log
+  #[serde(default = "slowlog_size")]
+  pub slowlog_size: usize,
+  #[serde(default)]
+  pub pubsub: PubSubConfig,
+  #[serde(default)]
+  pub log: LogConfig,
   //#[cfg(feature = "sentry")]
   //pub sentry_url: Option<String>,
 }
 
 impl Default for Config {
   fn default() -> Self {
+    let password = hash_password(DEFAULT_PASSWORD).expect("Failed to hash default password");
+    let default_admin = UserConfig::admin(password);
+
     Self {
-      conn: ConnectionConfig {
+      conn: vec![ConnectionConfig {
         enabled: true,
-        address: "0.0.0.0".to_owned(),
+        address: "127.0.0.1".to_owned(),
         port: 6380,
+        path: None,
+        mode: None,
+        owner: None,
+        group: None,
+        peer_users: SyncHashMap::default(),
         tls: false,
-      },
+        max_conns: None,
+        user: None,
+        require_client_cert: false,
+      }],
       tls: Some(TlsConfig::default()),
       autosave_interval: 60,
-      username: "admin".to_owned(),
-      password: "password".to_owned(),
+      expire_interval: expire_interval(),
+      expire_batch: expire_batch(),
+      expire_sweep: false,
+      save_compression: save_compression(),
+      users: SyncHashMap::from_iter([(DEFAULT_USERNAME.to_owned(), default_admin)]),
+      users_file: None,
+      username: None,
+      password: None,
+      allow_default_credentials: false,
+      strict_passwords: false,
       max_conns: 10000,
       max_message_size: 8 * 1024 * 1024,
       compress_threshold: 128 * 1204,
+      recv_buffer_size: recv_buffer_size(),
+      buffer_pool_size: buffer_pool_size(),
+      idle_timeout: idle_timeout(),
+      read_timeout: io_timeout(),
+      write_timeout: io_timeout(),
+      rate_limit: RateLimitConfig::default(),
+      auth_limit: AuthLimitConfig::default(),
+      audit: None,
+      metrics: None,
+      slowlog_threshold: slowlog_threshold(),
+      slowlog_size: slowlog_size(),
+      pubsub: PubSubConfig::default(),
+      log: LogConfig::default(),
       //#[cfg(feature = "sentry")]
       //sentry_url: None,
     }
   }
 }
 
+impl UserConfig {
+  pub fn admin(password: String) -> Self {
+    Self {
+      password,
+      grants: SyncHashMap::from_iter([("*".to_owned(), Role::Admin)]),
+    }
+  }
+}
+
+impl Config {
+  /// Merge users from `users_file` and the deprecated single-user fields into `users`.
+  pub fn load_users(&mut self) {
+    if let Some(path) = &self.users_file {
+      info!("Loading users from {}...", path);
+      let str = wrap_fatal!(
+        std::fs::read_to_string(path),
+        "Failed to read users file: {}"
+      );
+      let file: UsersFile = wrap_fatal!(toml::from_str(&str), "Failed to parse users file: {}");
+      for (name, user) in file.users {
+        if self.users.insert(name.clone(), user).is_some() {
+          warn!("User {} in users file overrides config", name);
+        }
+      }
+    }
+
+    if let (Some(username), Some(password)) = (self.username.take(), self.password.take()) {
+      warn!("`username` and `password` are deprecated, move them to [users]");
+      let user = UserConfig::admin(password);
+      self.users.entry(username).or_insert(user);
+    }
+
+    for (name, user) in &mut self.users {
+      if is_hashed(&user.password) {
+        continue;
+      }
+      if self.strict_passwords {
+        fatal!(
+          "User {} has a plain text password! Hash it with `void hash-password`",
+          name
+        );
+      }
+      warn!(
+        "User {} has a plain text password, which is deprecated! Hash it with `void hash-password`",
+        name
+      );
+      // so it's only ever checked against a hash
+      user.password = wrap_fatal!(hash_password(&user.password), "Failed to hash password: {}");
+    }
+  }
+}
+
 pub static CONFIG: Global<Config> = Global::new();
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MINIMAL: &str = r#"
+    autosave_interval = 60
+    max_conns = 100
+    max_message_size = 1024
+    compress_threshold = 128
+
+    [conn]
+    address = "127.0.0.1"
+    port = 6380
+  "#;
+
+  fn parse(extra: &str) -> Config {
+    toml::from_str(&format!("{}{}", extra, MINIMAL)).unwrap()
+  }
+
+  #[test]
+  fn save_compression_defaults_to_zstd() {
+    assert_eq!(parse("").save_compression, Some(Mode::Zstd));
+    let gzip = parse(r#"save_compression = "gzip""#);
+    assert_eq!(gzip.save_compression, Some(Mode::Gzip));
+  }
+
+  #[test]
+  fn timeouts_default_when_missing() {
+    let conf = parse("");
+    assert_eq!(conf.idle_timeout, 300);
+    assert_eq!(conf.read_timeout, 30);
+    assert_eq!(conf.write_timeout, 30);
+    assert_eq!(parse("idle_timeout = 0").idle_timeout, 0);
+  }
+
+  #[test]
+  fn save_compression_opt_out() {
+    let conf = parse(r#"save_compression = "none""#);
+    assert_eq!(conf.save_compression, None);
+
+    // written back out so it isn't read as the default again
+    let str = toml::to_string_pretty(&conf).unwrap();
+    assert!(str.contains(r#"save_compression = "none""#));
+    let conf: Config = toml::from_str(&str).unwrap();
+    assert_eq!(conf.save_compression, None);
+  }
+}
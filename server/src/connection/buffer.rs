@@ -0,0 +1,74 @@
+use crate::config::CONFIG;
+use bytes::BytesMut;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Mutex;
+
+// BUFFER POOL
+
+// idle buffers, none larger than `recv_buffer_size`
+static POOL: Mutex<Vec<BytesMut>> = Mutex::new(Vec::new());
+
+/// Bytes allocated for receive buffers, both in use and pooled.
+pub static BUFFER_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+pub fn pooled_buffers() -> usize {
+  POOL.lock().unwrap().len()
+}
+
+fn take() -> BytesMut {
+  POOL.lock().unwrap().pop().unwrap_or_default()
+}
+
+fn give(buf: BytesMut) {
+  if buf.capacity() <= CONFIG.recv_buffer_size {
+    let mut pool = POOL.lock().unwrap();
+    if pool.len() < CONFIG.buffer_pool_size {
+      pool.push(buf);
+      return;
+    }
+  }
+  BUFFER_MEMORY.fetch_sub(buf.capacity(), Relaxed);
+}
+
+// RECEIVE BUFFER
+
+/// Per-connection receive buffer, only allocated once a frame arrives and sized to fit it.
+#[derive(Default)]
+pub struct RecvBuffer(Option<BytesMut>);
+
+impl RecvBuffer {
+  /// Get `len` bytes to read a frame into, growing the buffer if needed.
+  pub fn get(&mut self, len: usize) -> &mut [u8] {
+    let buf = self.0.get_or_insert_with(take);
+    // contents are always overwritten, so only zero new space
+    if buf.len() < len {
+      let old = buf.capacity();
+      buf.resize(len, 0);
+      BUFFER_MEMORY.fetch_add(buf.capacity() - old, Relaxed);
+    }
+    &mut buf[..len]
+  }
+
+  /// Free the buffer if a large message grew it past `recv_buffer_size`.
+  #[inline]
+  pub fn shrink(&mut self) {
+    if self.capacity() > CONFIG.recv_buffer_size {
+      let buf = self.0.take().unwrap();
+      BUFFER_MEMORY.fetch_sub(buf.capacity(), Relaxed);
+    }
+  }
+
+  #[inline]
+  pub fn capacity(&self) -> usize {
+    self.0.as_ref().map_or(0, BytesMut::capacity)
+  }
+}
+
+impl Drop for RecvBuffer {
+  fn drop(&mut self) {
+    if let Some(buf) = self.0.take() {
+      give(buf);
+    }
+  }
+}
@@ -9,11 +9,12 @@ pub enum ErrorKind {
   Closed,
   Ignored,
   Continue,
+  TimedOut,
   Io(IoErrorKind),
   Status(Status),
 }
 
-pub use ErrorKind::{Closed, Continue, Ignored, Io};
+pub use ErrorKind::{Closed, Continue, Ignored, Io, TimedOut};
 
 impl From<Status> for ErrorKind {
   #[inline]
@@ -29,6 +30,7 @@ impl std::fmt::Display for ErrorKind {
       Closed => write!(f, "Connection closed"),
       Ignored => write!(f, "Ignoring unimplemented connection protocol spec"),
       Continue => write!(f, "Non-fatal error occurred, continuing..."),
+      TimedOut => write!(f, "Connection timed out"),
       Io(e) => write!(f, "I/O error: {e}"),
       ErrorKind::Status(ServerError) => write!(f, "Internal error (this is a bug!)"),
       ErrorKind::Status(s) => write!(f, "Request failed | status: {s}"),
@@ -65,7 +67,8 @@ impl From<IoError> for Error {
       | IoErrorKind::UnexpectedEof => Closed.into(),
       IoErrorKind::OutOfMemory | IoErrorKind::WriteZero => Io(e.kind()).into(),
       IoErrorKind::InvalidInput => Error::new(ServerError.into(), e.into()),
-      IoErrorKind::Interrupted | IoErrorKind::TimedOut => Continue.into(),
+      IoErrorKind::Interrupted => Continue.into(),
+      IoErrorKind::TimedOut => TimedOut.into(),
       _ => Error::new(BadRequest.into(), e.into()),
     }
   }
@@ -1,32 +1,63 @@
+mod buffer;
 mod error;
+mod stream;
+pub use buffer::*;
 pub use error::*;
-
-use crate::{config::CONFIG, logger::*, TableValue, DATABASE};
+pub use stream::*;
+
+use crate::audit::Audit;
+use crate::auth::{access, auth_failed, authenticate, locked_out, Access, User};
+use crate::clients::{self, Registration};
+use crate::config::{ConnectionConfig, Role, CONFIG};
+use crate::monitor::{self, Monitor};
+use crate::pubsub::{self, Subscriber};
+use crate::ratelimit::RateLimiter;
+use crate::slowlog::{self, Handling};
+use crate::{expiry, list, watch};
+use crate::{info::server_info, logger::*, metrics, TableValue, DATABASE};
 use protocol::*;
+use tracing::{info_span, Span};
 
-use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::future::{pending, Future};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed, Ordering::SeqCst};
 use std::time::{Duration, SystemTime};
 
 use scc::hash_map::Entry;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{sink, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
 
-use crate::compression::{read::read_to_bytes, Mode};
+use crate::compression::{read::bytes_to_bytes, Mode};
+use bytes::Bytes;
 use rmp_serde::{from_slice, to_vec};
-use bytes::BytesMut;
 
 // CONNECTION STRUCT
 
-pub struct Connection<S: RawStream>(BufReader<S>, BytesMut);
+pub struct Connection<S: RawStream>(Stream<S>, RecvBuffer, Traffic);
 
 impl<S: RawStream> From<S> for Connection<S> {
   #[inline(always)] // we only call this once, always inline
   fn from(stream: S) -> Self {
-    // add an extra 4 bytes for uncompressed length size
-    let len = CONFIG.max_message_size + 4;
-    Self(BufReader::new(stream), BytesMut::zeroed(len))
+    // nothing is allocated until the first request arrives
+    Self(
+      Stream::from(stream),
+      RecvBuffer::default(),
+      Traffic::default(),
+    )
   }
 }
 
+/// Framed bytes sent and received, before stream compression.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Traffic {
+  // size of the last request received
+  pub last_in: usize,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  // status of the last response sent, even if sending failed
+  pub last_status: Status,
+}
+
 impl<S: RawStream> Connection<S> {
   #[inline]
   pub async fn send(&mut self, res: Response) -> Result<(), Error> {
@@ -41,34 +72,137 @@ impl<S: RawStream> Connection<S> {
       msg.as_slice(),
     ]
     .concat();
-    check!(etc: self.0.write_all(&bytes).await)
+    self.2.bytes_out += bytes.len() as u64;
+    metrics::BYTES_OUT.fetch_add(bytes.len() as u64, Relaxed);
+    self.2.last_status = res.status;
+    deadline(CONFIG.write_timeout, async {
+      check!(etc: self.0.write_all(&bytes).await)?;
+      // pushes out buffered TLS records and stream compression frames
+      check!(etc: self.0.flush().await)
+    })
+    .await
   }
 
   #[inline]
   pub async fn recv(&mut self) -> Result<Request, Error> {
-    let len = check!(etc: self.0.read_u32_le().await)? as usize;
-    if len > CONFIG.max_message_size {
-      return Err(RequestTooLarge.into());
-    }
+    // waiting for the next request
+    let len = deadline(CONFIG.idle_timeout, async {
+      check!(etc: self.0.read_u32_le().await)
+    })
+    .await? as usize;
+    // header and message, the uncompressed length is ignored
+    self.2.last_in = len + 5;
+    self.2.bytes_in += self.2.last_in as u64;
+    metrics::BYTES_IN.fetch_add(self.2.last_in as u64, Relaxed);
+    // the rest of the frame should follow promptly
+    let req = deadline(CONFIG.read_timeout, async {
+      let comp = check!(etc: self.0.read_u8().await)?;
+      let full_len = match comp {
+        0 => None,
+        _ => Some(check!(etc: self.0.read_u32_le().await)? as usize),
+      };
+      // always read the whole frame, so a rejected one doesn't leave the rest to be parsed as the next
+      if len > CONFIG.max_message_size {
+        let (mut rest, mut sink) = ((&mut self.0).take(len as u64), sink());
+        if check!(etc: tokio::io::copy(&mut rest, &mut sink).await)? < len as u64 {
+          return Err(Closed.into());
+        }
+        return Err(RequestTooLarge.into());
+      }
+      let buf = self.1.get(len);
+      check!(etc: self.0.read_exact(buf).await)?;
 
-    let comp = check!(etc: self.0.read_u8().await)?;
-    if comp == 0 {
-      check!(etc: self.0.read_exact(&mut self.1[0..len]).await)?;
-      check!(req: from_slice(&self.1[0..len]))
-    } else {
+      let Some(full_len) = full_len else {
+        return check!(req: from_slice(buf));
+      };
       let mode = check!(req: Mode::try_from(comp))?;
-      let full_len = check!(etc: self.0.read_u32_le().await)? as usize;
-      let uncompressed = check!(req: read_to_bytes(&mut self.0, full_len, mode).await)?;
+      if full_len > CONFIG.max_message_size {
+        return Err(RequestTooLarge.into());
+      }
+      let compressed = Bytes::copy_from_slice(buf);
+      let uncompressed = check!(req: bytes_to_bytes(compressed, full_len, mode).await)?;
+      metrics::compressed(mode, len, full_len);
       check!(req: from_slice(&uncompressed))
+    })
+    .await;
+
+    self.1.shrink();
+    req
+  }
+
+  /// Wait until the next request starts arriving or the stream ends, without consuming anything.
+  /// Cancel safe, unlike `recv`.
+  #[inline]
+  pub async fn readable(&mut self) {
+    let _ = self.0.fill_buf().await;
+  }
+
+  /// Wait until the stream ends, leaving any requests that arrive meanwhile unread. Cancel safe.
+  #[inline]
+  pub async fn closed(&mut self) {
+    match self.0.fill_buf().await {
+      Ok([]) | Err(_) => {}
+      Ok(_) => pending().await,
     }
   }
 
+  /// Bytes currently allocated for this connection's receive buffer.
+  #[inline]
+  pub fn memory(&self) -> usize {
+    self.1.capacity()
+  }
+
+  #[inline]
+  pub fn traffic(&self) -> Traffic {
+    self.2
+  }
+
+  #[inline]
+  pub fn is_compressed(&self) -> bool {
+    self.0.is_compressed()
+  }
+
+  /// Switch to full-duplex stream compression. Must be called after sending the response.
+  #[inline]
+  pub fn compress(&mut self, mode: Mode) -> Result<(), Error> {
+    check!(srv: self.0.compress(mode))
+  }
+
   #[inline]
   pub async fn close(&mut self) -> Result<(), Error> {
     self.0.shutdown().await.map_err(|_| Closed.into())
   }
 }
 
+// PEER
+
+/// Where a connection came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+  Tcp(SocketAddr),
+  Unix { uid: Option<u32> },
+}
+
+impl Peer {
+  #[inline]
+  pub fn ip(&self) -> Option<IpAddr> {
+    match self {
+      Self::Tcp(addr) => Some(addr.ip()),
+      Self::Unix { .. } => None,
+    }
+  }
+}
+
+impl std::fmt::Display for Peer {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Tcp(addr) => write!(f, "{addr}"),
+      Self::Unix { uid: Some(uid) } => write!(f, "unix:{uid}"),
+      Self::Unix { uid: None } => write!(f, "unix"),
+    }
+  }
+}
+
 pub trait RawStream: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static {}
 impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static> RawStream for S {}
 
@@ -82,6 +216,17 @@ macro_rules! check {
 
 pub use check;
 
+// fail with `TimedOut` if `fut` takes longer than `secs` seconds, 0 waits forever
+#[inline]
+async fn deadline<T>(secs: u64, fut: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+  match secs {
+    0 => fut.await,
+    secs => timeout(Duration::from_secs(secs), fut)
+      .await
+      .unwrap_or_else(|_| Err(TimedOut.into())),
+  }
+}
+
 // CONNECTION HANDLER
 
 pub static CURRENT_CONNS: AtomicUsize = AtomicUsize::new(0);
@@ -104,14 +249,77 @@ macro_rules! send {
 }
 
 #[inline(always)] // we only call this once, always inline
-pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
-  CURRENT_CONNS.fetch_add(1, SeqCst);
+pub async fn handle_conn<S: RawStream>(
+  conn: &mut Connection<S>,
+  peer: Peer,
+  user: Option<User>,
+  listener: &'static ConnectionConfig,
+) {
+  let client = Registration::new(peer, user, listener);
+  let span = info_span!("conn", id = client.id(), %peer);
+  let slot = SpanSlot::new(span.clone());
+  let serving = serve(conn, peer, user, &client, &span, &slot);
+  slot.instrument(serving).await;
+}
+
+#[inline(always)] // we only call this once, always inline
+async fn serve<S: RawStream>(
+  conn: &mut Connection<S>,
+  peer: Peer,
+  mut user: Option<User>,
+  client: &Registration,
+  // logs are in the connection's span, or the request's while one is handled
+  span: &Span,
+  slot: &SpanSlot,
+) {
+  metrics::CONNS_TOTAL.fetch_add(1, Relaxed);
   info!("Connection established {}", fmt_conns());
 
-  let mut authenticated = false;
+  let mut limiter = RateLimiter::new(peer);
+  let mut auth_failures = 0;
+  // outside the loop, so requests are still audited if sending the response fails
+  let mut audit = Audit::default();
+  // the request being handled, recorded once it's answered
+  let mut handling = Handling::default();
+  let mut monitor: Option<Monitor> = None;
+  let mut subscriber: Option<Subscriber> = None;
 
   loop {
-    let request = match conn.recv().await {
+    if handling.active() {
+      client.update(handling.action, user, conn.traffic(), conn.memory());
+      handling.finish(peer, user, conn.traffic().last_status);
+      slot.set(span.clone());
+    }
+
+    // push monitored requests and messages until the client sends something
+    if monitor.is_some() || subscriber.is_some() {
+      let pushed = tokio::select! {
+        _ = conn.readable() => None,
+        monitor = async { monitor.as_mut().unwrap().recv().await }, if monitor.is_some() => {
+          Some(Payload::Monitor { monitor })
+        }
+        payload = async { subscriber.as_mut().unwrap().recv().await }, if subscriber.is_some() => {
+          Some(payload)
+        }
+        _ = client.killed() => {
+          info!("Connection {} killed", client.id());
+          break;
+        }
+      };
+      if let Some(payload) = pushed {
+        send!(conn, Response::ok(payload));
+        continue;
+      }
+    }
+
+    let request = tokio::select! {
+      req = conn.recv() => req,
+      _ = client.killed() => {
+        info!("Connection {} killed", client.id());
+        break;
+      }
+    };
+    let request = match request {
       Ok(r) => r,
       Err(e) => match e.kind {
         Closed => break,
@@ -120,6 +328,10 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
           continue;
         }
         Continue => continue,
+        TimedOut => {
+          warn!("{e}");
+          break;
+        }
         Io(_) => {
           error!("{e}");
           break;
@@ -131,6 +343,54 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
         }
       },
     };
+    handling.start(&request, conn.traffic().last_in);
+    slot.set(info_span!(
+      parent: span,
+      "request",
+      action = request.action(),
+      user = user.map(|u| u.name)
+    ));
+
+    if !limiter.allow(user, conn.traffic().last_in).await {
+      trace!("Request rate limited | peer: {}", peer);
+      send!(conn, Response::status(RateLimited));
+      continue;
+    }
+
+    audit = Audit::new(&request, peer, user);
+
+    match (access(&request), user) {
+      (Access::Public, _) | (Access::Authenticated, Some(_)) => {}
+      (_, None) => {
+        trace!("Request denied | AUTH required");
+        audit.log(Unauthorized).await;
+        send!(conn, Response::status(Unauthorized));
+        continue;
+      }
+      (Access::Admin, Some(u)) => {
+        if !u.is_admin() {
+          trace!("Request denied | user: {}, admin required", u.name);
+          audit.log(PermissionDenied).await;
+          send!(conn, Response::status(PermissionDenied));
+          continue;
+        }
+      }
+      (Access::Table(role, table), Some(u)) => {
+        if !u.can(role, table) {
+          trace!(
+            "Request denied | user: {}, table: {}, role: {:?}",
+            u.name,
+            table,
+            role
+          );
+          audit.log(PermissionDenied).await;
+          send!(conn, Response::status(PermissionDenied));
+          continue;
+        }
+      }
+    }
+    // only requests that are actually run
+    monitor::publish(&request, peer, user);
 
     match request {
       Request::Ping => {
@@ -139,51 +399,214 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
       }
 
       Request::Auth { username, password } => {
-        let conf = &*CONFIG;
-        if username == conf.username && password == conf.password {
-          authenticated = true;
-          trace!("AUTH succeeded");
+        if locked_out(peer) {
+          // a failure like any other, but nothing can succeed until the lockout ends
+          auth_failures += 1;
+          trace!("AUTH refused | peer: {} is locked out", peer);
+          auth_failed(peer, auth_failures).await;
+          send!(conn, Response::status(Unauthorized));
+          warn!("AUTH from locked out peer {}, closing", peer);
+          break;
+        } else if let Some(u) = authenticate(username, password).await {
+          user = Some(u);
+          auth_failures = 0;
+          if let Some(sub) = &subscriber {
+            sub.set_user(user).await;
+          }
+          trace!("AUTH succeeded | user: {}", u.name);
           send!(conn, Response::OK);
         } else {
+          auth_failures += 1;
           trace!("AUTH failed with invalid credentials");
+          let keep = auth_failed(peer, auth_failures).await;
           send!(conn, Response::status(Unauthorized));
+          if !keep {
+            warn!("Too many failed AUTH attempts from {}, closing", peer);
+            break;
+          }
         }
       }
 
-      Request::ListTables if authenticated => {
+      Request::CompressStream { mode } => {
+        trace!("COMPRESS STREAM requested | mode: {}", mode);
+        match Mode::try_from(mode) {
+          Ok(mode) if mode.streamable() && !conn.is_compressed() => {
+            send!(conn, Response::OK);
+            if let Err(e) = conn.compress(mode) {
+              error!("{e}");
+              break;
+            }
+          }
+          _ => send!(conn, Response::status(BadRequest)),
+        }
+      }
+
+      Request::Info => {
+        trace!("INFO requested");
+        let info = server_info(user).await;
+        send!(conn, Response::ok(Payload::Info { info }));
+      }
+
+      Request::SlowLogGet { count } => {
+        trace!("SLOWLOG GET requested | count: {:?}", count);
+        let slowlog = slowlog::get(count);
+        send!(conn, Response::ok(Payload::SlowLog { slowlog }));
+      }
+
+      Request::SlowLogReset => {
+        trace!("SLOWLOG RESET requested");
+        slowlog::reset();
+        send!(conn, Response::OK);
+      }
+
+      Request::Monitor { table, actions } => {
+        trace!(
+          "MONITOR requested | table: {:?}, actions: {:?}",
+          table,
+          actions
+        );
+        // replaces any previous filters
+        monitor = Some(Monitor::new(table, actions));
+        send!(conn, Response::OK);
+      }
+
+      Request::ClientList => {
+        trace!("CLIENT LIST requested");
+        let clients = clients::list().await;
+        send!(conn, Response::ok(Payload::Clients { clients }));
+      }
+
+      Request::ClientKill { id, peer, user } => {
+        trace!(
+          "CLIENT KILL requested | id: {:?}, peer: {:?}, user: {:?}",
+          id,
+          peer,
+          user
+        );
+        if id.is_none() && peer.is_none() && user.is_none() {
+          send!(conn, Response::status(BadRequest));
+        } else {
+          let killed = clients::kill(id, peer.as_deref(), user.as_deref()).await;
+          send!(conn, Response::ok(Payload::Killed { killed }));
+        }
+      }
+
+      Request::Subscribe { channels, patterns } => {
+        trace!(
+          "SUBSCRIBE requested | channels: {:?}, patterns: {:?}",
+          channels,
+          patterns
+        );
+        let sub = subscriber.get_or_insert_with(|| Subscriber::new(client.id()));
+        let channels = channels.unwrap_or_default();
+        if !sub.subscribe(channels, patterns.unwrap_or_default()).await {
+          if sub.count() == 0 {
+            subscriber = None;
+          }
+          send!(conn, Response::status(SubscriptionLimit));
+          continue;
+        }
+        let subscriptions = sub.count();
+        send!(conn, Response::ok(Payload::Subscriptions { subscriptions }));
+      }
+
+      Request::Unsubscribe { channels, patterns } => {
+        trace!(
+          "UNSUBSCRIBE requested | channels: {:?}, patterns: {:?}",
+          channels,
+          patterns
+        );
+        if let Some(sub) = &mut subscriber {
+          sub.unsubscribe(channels, patterns);
+        }
+        let subscriptions = subscriber.as_ref().map_or(0, Subscriber::count);
+        if subscriptions == 0 {
+          subscriber = None;
+        }
+        send!(conn, Response::ok(Payload::Subscriptions { subscriptions }));
+      }
+
+      Request::Publish { channel, data } => {
+        trace!("PUBLISH requested | channel: {}", channel);
+        let receivers = pubsub::publish(&channel, data).await;
+        send!(conn, Response::ok(Payload::Receivers { receivers }));
+      }
+
+      Request::KeyspaceSubscribe { table, key } => {
+        trace!(
+          "KEYSPACE SUBSCRIBE requested | table: {}, key: {:?}",
+          table,
+          key
+        );
+        let sub = subscriber.get_or_insert_with(|| Subscriber::new(client.id()));
+        if !sub.subscribe_keyspace(user, table, key).await {
+          if sub.count() == 0 {
+            subscriber = None;
+          }
+          send!(conn, Response::status(SubscriptionLimit));
+          continue;
+        }
+        let subscriptions = sub.count();
+        send!(conn, Response::ok(Payload::Subscriptions { subscriptions }));
+      }
+
+      Request::KeyspaceUnsubscribe { table, key } => {
+        trace!(
+          "KEYSPACE UNSUBSCRIBE requested | table: {:?}, key: {:?}",
+          table,
+          key
+        );
+        if let Some(sub) = &mut subscriber {
+          sub.unsubscribe_keyspace(user, table, key).await;
+        }
+        let subscriptions = subscriber.as_ref().map_or(0, Subscriber::count);
+        if subscriptions == 0 {
+          subscriber = None;
+        }
+        send!(conn, Response::ok(Payload::Subscriptions { subscriptions }));
+      }
+
+      Request::ListTables => {
         trace!("LIST TABLE requested");
         let mut tables = Vec::<String>::with_capacity(DATABASE.len());
         let mut entry = DATABASE.first_entry_async().await;
         while let Some(e) = &entry {
-          tables.push(e.key().to_owned());
+          if user.is_some_and(|u| u.can(Role::Read, e.key())) {
+            tables.push(e.key().to_owned());
+          }
           entry = entry.unwrap().next_async().await;
         }
         send!(conn, Response::ok(Payload::Tables { tables }));
       }
 
-      Request::InsertTable { table, contents } if authenticated => {
+      Request::InsertTable { table, contents } => {
         trace!("INSERT TABLE requested | table: {}", table);
         if let Entry::Vacant(entry) = DATABASE.entry_async(table).await {
           let tbl = crate::Table::default();
           if let Some(prot_tbl) = contents {
+            let table = entry.key().clone();
             // build Table from InsertTable
             let mut entry = prot_tbl.first_entry_async().await;
             while let Some(e) = &entry {
               let key = e.key().to_owned();
               let InsertTableValue { value, lifetime } = e.get().clone();
               let expiry = lifetime.map(|exp| SystemTime::now() + Duration::from_secs(exp));
+              if let Some(expiry) = expiry {
+                expiry::track(&table, &key, expiry);
+              }
               let _ = tbl.insert_async(key, TableValue { value, expiry }).await;
               entry = entry.unwrap().next_async().await;
             }
           }
-          entry.insert_entry(tbl);
+          let entry = entry.insert_entry(tbl);
+          pubsub::notify(KeyspaceAction::InsertTable, entry.key(), None).await;
           send!(conn, Response::OK);
         } else {
           send!(conn, Response::status(AlreadyExists));
         }
       }
 
-      Request::GetTable { table } if authenticated => {
+      Request::GetTable { table } => {
         trace!("GET TABLE requested | table: {}", table);
         if let Some(table) = DATABASE.get_async(&table).await {
           let table = table.clone();
@@ -194,13 +617,16 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
         }
       }
 
-      Request::DeleteTable { table } if authenticated => {
+      Request::DeleteTable { table } => {
         trace!("DELETE TABLE requested | table: {}", table);
-        let _ = DATABASE.remove_async(&table).await;
+        if DATABASE.remove_async(&table).await.is_some() {
+          expiry::untrack_table(&table);
+          pubsub::notify(KeyspaceAction::DeleteTable, &table, None).await;
+        }
         send!(conn, Response::OK);
       }
 
-      Request::List { table } if authenticated => {
+      Request::List { table } => {
         trace!("LIST requested | table: {}", table);
         if let Some(tbl) = DATABASE.get_async(&table).await {
           let mut keys = Vec::<String>::with_capacity(tbl.len());
@@ -215,12 +641,14 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
         }
       }
 
-      Request::Get { table, key } if authenticated => {
+      Request::Get { table, key } => {
         trace!("GET requested | table: {}, key: {}", table, key);
         if let Some(tbl) = DATABASE.get_async(&table).await {
           if let Some(value) = tbl.get_async(&key).await {
             if value.expiry.is_some_and(|st| st <= SystemTime::now()) {
               let _ = value.remove();
+              metrics::EXPIRED_KEYS.fetch_add(1, Relaxed);
+              pubsub::notify(KeyspaceAction::Expire, &table, Some(&key)).await;
               send!(conn, Response::status(KeyExpired));
             } else {
               let value = value.clone();
@@ -235,21 +663,53 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
         }
       }
 
-      Request::Delete { table, key } if authenticated => {
+      Request::Delete { table, key } => {
         trace!("DELETE requested | table: {}, key: {}", table, key);
-        if let Some(table) = DATABASE.get_async(&table).await {
-          let _ = table.remove_async(&key).await;
+        if let Some(tbl) = DATABASE.get_async(&table).await {
+          if let Some((_, value)) = tbl.remove_async(&key).await {
+            if value.expiry.is_some() {
+              expiry::untrack(&table, &key);
+            }
+            pubsub::notify(KeyspaceAction::Delete, &table, Some(&key)).await;
+          }
         }
         send!(conn, Response::OK);
       }
 
-      Request::Insert { table, key, value } if authenticated => {
+      Request::Watch {
+        table,
+        key,
+        timeout,
+      } => {
+        trace!(
+          "WATCH requested | table: {}, key: {}, timeout: {:?}",
+          table,
+          key,
+          timeout
+        );
+        let res = tokio::select! {
+          res = watch::watch(table, key, timeout) => res,
+          _ = conn.closed() => break,
+          _ = client.killed() => {
+            info!("Connection {} killed", client.id());
+            break;
+          }
+        };
+        send!(conn, res);
+      }
+
+      Request::Insert { table, key, value } => {
         trace!("INSERT requested | table: {}, key: {}", table, key);
-        if let Some(table) = DATABASE.get_async(&table).await {
-          if let Entry::Vacant(entry) = table.entry_async(key).await {
+        if let Some(tbl) = DATABASE.get_async(&table).await {
+          if let Entry::Vacant(entry) = tbl.entry_async(key).await {
             let InsertTableValue { value, lifetime } = value;
             let expiry = lifetime.map(|exp| SystemTime::now() + Duration::from_secs(exp));
-            entry.insert_entry(TableValue { value, expiry });
+            if let Some(expiry) = expiry {
+              expiry::track(&table, entry.key(), expiry);
+            }
+            let entry = entry.insert_entry(TableValue { value, expiry });
+            pubsub::notify(KeyspaceAction::Insert, &table, Some(entry.key())).await;
+            send!(conn, Response::OK);
           } else {
             send!(conn, Response::status(AlreadyExists));
           }
@@ -258,12 +718,136 @@ pub async fn handle_conn<S: RawStream>(conn: &mut Connection<S>) {
         }
       }
 
+      Request::Push {
+        table,
+        key,
+        end,
+        values,
+      } => {
+        trace!(
+          "PUSH requested | table: {}, key: {}, end: {:?}",
+          table,
+          key,
+          end
+        );
+        match list::push(&table, key, end, values).await {
+          Ok(length) => send!(conn, Response::ok(Payload::Length { length })),
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
+      Request::Pop {
+        table,
+        key,
+        end,
+        count,
+      } => {
+        trace!(
+          "POP requested | table: {}, key: {}, end: {:?}, count: {:?}",
+          table,
+          key,
+          end,
+          count
+        );
+        let count = count.unwrap_or(1) as usize;
+        match list::pop(&table, &key, end, count).await {
+          Ok(values) => send!(conn, Response::ok(Payload::Values { values })),
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
+      Request::BlockingPop {
+        table,
+        key,
+        end,
+        timeout,
+      } => {
+        trace!(
+          "BLOCKING POP requested | table: {}, key: {}, end: {:?}, timeout: {:?}",
+          table,
+          key,
+          end,
+          timeout
+        );
+        let popped = tokio::select! {
+          popped = list::blocking_pop(&table, &key, end, timeout) => popped,
+          _ = conn.closed() => break,
+          _ = client.killed() => {
+            info!("Connection {} killed", client.id());
+            break;
+          }
+        };
+        match popped {
+          Ok(value) => {
+            let values = vec![value];
+            send!(conn, Response::ok(Payload::Values { values }));
+          }
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
+      Request::Range {
+        table,
+        key,
+        start,
+        stop,
+      } => {
+        trace!(
+          "RANGE requested | table: {}, key: {}, start: {}, stop: {}",
+          table,
+          key,
+          start,
+          stop
+        );
+        match list::get_range(&table, &key, start, stop).await {
+          Ok(values) => send!(conn, Response::ok(Payload::Values { values })),
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
+      Request::Trim {
+        table,
+        key,
+        start,
+        stop,
+      } => {
+        trace!(
+          "TRIM requested | table: {}, key: {}, start: {}, stop: {}",
+          table,
+          key,
+          start,
+          stop
+        );
+        match list::trim(&table, &key, start, stop).await {
+          Ok(length) => send!(conn, Response::ok(Payload::Length { length })),
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
+      Request::Length { table, key } => {
+        trace!("LENGTH requested | table: {}, key: {}", table, key);
+        match list::length(&table, &key).await {
+          Ok(length) => send!(conn, Response::ok(Payload::Length { length })),
+          Err(s) => send!(conn, Response::status(s)),
+        }
+      }
+
       // malformed requests will be caught before this point
-      _ => send!(conn, Response::status(Unauthorized)),
+      _ => send!(conn, Response::status(BadRequest)),
     }
+
+    audit.log(conn.traffic().last_status).await;
   }
 
+  audit.log(conn.traffic().last_status).await;
+  handling.finish(peer, user, conn.traffic().last_status);
+
+  trace!(
+    "Receive buffers | connection: {} bytes, total: {} bytes, pooled: {}",
+    conn.memory(),
+    BUFFER_MEMORY.load(Relaxed),
+    pooled_buffers()
+  );
   let _ = conn.close().await;
-  CURRENT_CONNS.fetch_sub(1, SeqCst);
   info!("Connection closed {}", fmt_conns());
 }
@@ -0,0 +1,115 @@
+use super::RawStream;
+use crate::compression::read::{reader_async, AsyncReader};
+use crate::compression::write::{writer_async, AsyncWriter};
+use crate::compression::Mode;
+use crate::AnyResult as Result;
+
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult};
+use std::mem::replace;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{split, AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+
+// STREAM
+
+/// Connection byte stream, optionally wrapped in full-duplex stream compression.
+pub enum Stream<S: RawStream> {
+  Raw(BufReader<S>),
+  Compressed(BufReader<AsyncReader>, AsyncWriter),
+  // only observable if switching compression modes failed
+  Closed,
+}
+
+impl<S: RawStream> Stream<S> {
+  #[inline]
+  pub fn is_compressed(&self) -> bool {
+    matches!(self, Self::Compressed(..))
+  }
+
+  /// Wrap both directions of the stream in `mode`.
+  /// Anything already buffered is treated as compressed.
+  pub fn compress(&mut self, mode: Mode) -> Result<()> {
+    let Self::Raw(raw) = replace(self, Self::Closed) else {
+      return Err("Stream is already compressed".into());
+    };
+    let (read, write) = split(raw);
+    let read = reader_async(BufReader::new(read), mode)?;
+    let write = writer_async(write, mode)?;
+    *self = Self::Compressed(BufReader::new(read), write);
+    Ok(())
+  }
+}
+
+impl<S: RawStream> From<S> for Stream<S> {
+  #[inline]
+  fn from(stream: S) -> Self {
+    Self::Raw(BufReader::new(stream))
+  }
+}
+
+// ASYNC IMPLS
+
+#[inline]
+fn closed<T>() -> Poll<IoResult<T>> {
+  Poll::Ready(Err(IoErrorKind::BrokenPipe.into()))
+}
+
+impl<S: RawStream> AsyncRead for Stream<S> {
+  #[inline]
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<IoResult<()>> {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).poll_read(cx, buf),
+      Self::Compressed(r, _) => Pin::new(r).poll_read(cx, buf),
+      Self::Closed => closed(),
+    }
+  }
+}
+
+impl<S: RawStream> AsyncBufRead for Stream<S> {
+  #[inline]
+  fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<IoResult<&[u8]>> {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).poll_fill_buf(cx),
+      Self::Compressed(r, _) => Pin::new(r).poll_fill_buf(cx),
+      Self::Closed => closed(),
+    }
+  }
+
+  #[inline]
+  fn consume(self: Pin<&mut Self>, amt: usize) {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).consume(amt),
+      Self::Compressed(r, _) => Pin::new(r).consume(amt),
+      Self::Closed => {}
+    }
+  }
+}
+
+impl<S: RawStream> AsyncWrite for Stream<S> {
+  #[inline]
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<IoResult<usize>> {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).poll_write(cx, buf),
+      Self::Compressed(_, w) => w.as_mut().poll_write(cx, buf),
+      Self::Closed => closed(),
+    }
+  }
+
+  #[inline]
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<IoResult<()>> {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).poll_flush(cx),
+      Self::Compressed(_, w) => w.as_mut().poll_flush(cx),
+      Self::Closed => closed(),
+    }
+  }
+
+  #[inline]
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<IoResult<()>> {
+    match self.get_mut() {
+      Self::Raw(s) => Pin::new(s).poll_shutdown(cx),
+      Self::Compressed(_, w) => w.as_mut().poll_shutdown(cx),
+      Self::Closed => Poll::Ready(Ok(())),
+    }
+  }
+}
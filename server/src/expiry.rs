@@ -0,0 +1,183 @@
+use crate::config::CONFIG;
+use crate::{metrics, pubsub, DATABASE};
+use protocol::KeyspaceAction;
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+// DEADLINES
+
+// one entry per key given a lifetime, dropped when the key is deleted or given a new one
+// keys that expire on access are left until their deadline, so removal re-checks the key itself
+#[derive(Default)]
+struct Deadlines {
+  // (expiry, table, key), soonest first
+  queue: BTreeSet<(SystemTime, String, String)>,
+  // table -> key -> expiry, to find a key's entry in `queue`
+  keys: HashMap<String, HashMap<String, SystemTime>>,
+}
+
+impl Deadlines {
+  fn insert(&mut self, table: &str, key: &str, expiry: SystemTime) {
+    let keys = self.keys.entry(table.to_owned()).or_default();
+    if let Some(old) = keys.insert(key.to_owned(), expiry) {
+      self.queue.remove(&(old, table.to_owned(), key.to_owned()));
+    }
+    self
+      .queue
+      .insert((expiry, table.to_owned(), key.to_owned()));
+  }
+
+  fn remove(&mut self, table: &str, key: &str) {
+    let Some(keys) = self.keys.get_mut(table) else {
+      return;
+    };
+    if let Some(expiry) = keys.remove(key) {
+      self
+        .queue
+        .remove(&(expiry, table.to_owned(), key.to_owned()));
+    }
+    if keys.is_empty() {
+      self.keys.remove(table);
+    }
+  }
+
+  fn remove_table(&mut self, table: &str) {
+    for (key, expiry) in self.keys.remove(table).unwrap_or_default() {
+      self.queue.remove(&(expiry, table.to_owned(), key));
+    }
+  }
+
+  // the soonest deadline, if it has passed
+  fn pop_due(&mut self, now: SystemTime) -> Option<(String, String)> {
+    if self.queue.first().is_none_or(|(st, ..)| *st > now) {
+      return None;
+    }
+    let (_, table, key) = self.queue.pop_first()?;
+    self.remove(&table, &key);
+    Some((table, key))
+  }
+}
+
+static DEADLINES: LazyLock<Mutex<Deadlines>> = LazyLock::new(Default::default);
+
+/// Remember when `key` in `table` expires, so the sweeper can remove it if it's never accessed.
+/// Replaces any deadline the key had before.
+pub fn track(table: &str, key: &str, expiry: SystemTime) {
+  DEADLINES.lock().unwrap().insert(table, key, expiry);
+}
+
+/// Forget `key` in `table`, which was deleted before it expired.
+pub fn untrack(table: &str, key: &str) {
+  DEADLINES.lock().unwrap().remove(table, key);
+}
+
+/// Forget every key in `table`, which was deleted.
+pub fn untrack_table(table: &str) {
+  DEADLINES.lock().unwrap().remove_table(table);
+}
+
+/// Track every key with a lifetime in the loaded database.
+pub async fn track_loaded() {
+  let mut loaded = Vec::new();
+  DATABASE
+    .scan_async(|table, tbl| {
+      tbl.scan(|key, v| {
+        if let Some(expiry) = v.expiry {
+          loaded.push((table.clone(), key.clone(), expiry));
+        }
+      })
+    })
+    .await;
+  let mut deadlines = DEADLINES.lock().unwrap();
+  for (table, key, expiry) in loaded {
+    deadlines.insert(&table, &key, expiry);
+  }
+}
+
+// up to `max` deadlines that have passed
+fn due(max: usize) -> Vec<(String, String)> {
+  let now = SystemTime::now();
+  let mut deadlines = DEADLINES.lock().unwrap();
+  let mut due = Vec::new();
+  while due.len() < max {
+    let Some(next) = deadlines.pop_due(now) else {
+      break;
+    };
+    due.push(next);
+  }
+  due
+}
+
+// SWEEPER
+
+/// Periodically remove expired keys that nothing has touched, so their `EXPIRE` events still fire.
+/// Only keys whose deadline passed are visited, and only while something listens for them
+/// (unless `expire_sweep` is set); otherwise they're left for the next access to remove.
+pub async fn sweep() {
+  let mut interval = interval(Duration::from_secs(CONFIG.expire_interval.max(1)));
+  interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  loop {
+    interval.tick().await;
+    if !CONFIG.expire_sweep && !pubsub::listening() {
+      // nothing would see them go, so leave them to be removed when next accessed
+      due(usize::MAX);
+      continue;
+    }
+
+    // the rest wait for the next tick, so writers aren't held up for long
+    for (table, key) in due(CONFIG.expire_batch.max(1)) {
+      let Some(tbl) = DATABASE.get_async(&table).await else {
+        continue;
+      };
+      let now = SystemTime::now();
+      let expired = tbl.remove_if_async(&key, |v| v.expiry.is_some_and(|st| st <= now));
+      if expired.await.is_some() {
+        drop(tbl);
+        metrics::EXPIRED_KEYS.fetch_add(1, Relaxed);
+        pubsub::notify(KeyspaceAction::Expire, &table, Some(&key)).await;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn due_deadlines_come_soonest_first() {
+    let now = SystemTime::now();
+    track("t", "later", now - Duration::from_secs(1));
+    track("t", "future", now + Duration::from_secs(60));
+    track("t", "first", now - Duration::from_secs(2));
+    let key = |k: &str| ("t".to_owned(), k.to_owned());
+    assert_eq!(due(1), vec![key("first")]);
+    assert_eq!(due(usize::MAX), vec![key("later")]);
+    assert!(due(usize::MAX).is_empty());
+    assert_eq!(DEADLINES.lock().unwrap().queue.len(), 1);
+  }
+
+  #[test]
+  fn deleted_and_renewed_keys_leave_no_stale_deadlines() {
+    let now = SystemTime::now();
+    let mut deadlines = Deadlines::default();
+    deadlines.insert("t", "renewed", now - Duration::from_secs(2));
+    deadlines.insert("t", "renewed", now + Duration::from_secs(60));
+    deadlines.insert("t", "deleted", now - Duration::from_secs(1));
+    deadlines.remove("t", "deleted");
+    deadlines.insert("gone", "a", now - Duration::from_secs(1));
+    deadlines.insert("gone", "b", now + Duration::from_secs(60));
+    deadlines.remove_table("gone");
+
+    assert_eq!(deadlines.queue.len(), 1);
+    assert_eq!(deadlines.pop_due(now), None);
+    let later = now + Duration::from_secs(61);
+    let renewed = ("t".to_owned(), "renewed".to_owned());
+    assert_eq!(deadlines.pop_due(later), Some(renewed));
+    assert!(deadlines.keys.is_empty());
+  }
+}
@@ -0,0 +1,96 @@
+use crate::auth::User;
+use crate::config::{Role, CONFIG};
+use crate::connection::{BUFFER_MEMORY, CURRENT_CONNS};
+use crate::{metrics, TableValue, DATABASE, STARTED};
+use protocol::{Connections, Limits, Persistence, PrimitiveValue, ServerInfo};
+
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// MEMORY ESTIMATES
+
+// heap usage of a value, excluding the value itself
+fn heap_size(value: &PrimitiveValue) -> usize {
+  match value {
+    PrimitiveValue::String(s) => s.capacity(),
+    PrimitiveValue::Array(a) => {
+      let items = a.capacity() * size_of::<PrimitiveValue>();
+      items + a.iter().map(heap_size).sum::<usize>()
+    }
+    _ => 0,
+  }
+}
+
+// one hash map entry, ignoring the map's spare capacity
+#[inline]
+fn entry_size(key: &str, value: &TableValue) -> usize {
+  size_of::<String>() + key.len() + size_of::<TableValue>() + heap_size(&value.value)
+}
+
+// INFO
+
+/// Collect server state for `INFO`, only listing tables `user` can read.
+/// Memory is only estimated for admins, since it walks every key.
+pub async fn server_info(user: Option<User>) -> ServerInfo {
+  let conf = &*CONFIG;
+  let mut tables = BTreeMap::new();
+  let admin = user.is_some_and(|u| u.is_admin());
+  let mut memory = BUFFER_MEMORY.load(Relaxed);
+
+  let mut entry = DATABASE.first_entry_async().await;
+  while let Some(e) = &entry {
+    let (name, table) = (e.key(), e.get());
+    if admin {
+      let mut table_memory = 0;
+      table
+        .scan_async(|k, v| table_memory += entry_size(k, v))
+        .await;
+      memory += size_of::<String>() + name.len() + table_memory;
+    }
+
+    if user.is_some_and(|u| u.can(Role::Read, name)) {
+      tables.insert(name.to_owned(), table.len() as u64);
+    }
+    entry = entry.unwrap().next_async().await;
+  }
+
+  let saves = metrics::SAVES.load(Relaxed);
+  let last_save = Some(metrics::SAVE_TIME.load(Relaxed))
+    .filter(|_| saves > 0)
+    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+  // an autosave may be running, so allow for twice the interval
+  let since_save = last_save.map(|t| SystemTime::now().duration_since(t).unwrap_or_default());
+  let since_start = STARTED.elapsed();
+  let overdue = Duration::from_secs(conf.autosave_interval * 2);
+  let healthy = since_save.unwrap_or(since_start) <= overdue;
+
+  ServerInfo {
+    version: env!("CARGO_PKG_VERSION").to_owned(),
+    uptime: since_start.as_secs(),
+    limits: Limits {
+      max_conns: conf.max_conns as u64,
+      max_message_size: conf.max_message_size as u64,
+      compress_threshold: conf.compress_threshold as u64,
+      // disabled timeouts are reported as null
+      idle_timeout: Some(conf.idle_timeout).filter(|&secs| secs > 0),
+      read_timeout: Some(conf.read_timeout).filter(|&secs| secs > 0),
+      write_timeout: Some(conf.write_timeout).filter(|&secs| secs > 0),
+    },
+    connections: Connections {
+      current: CURRENT_CONNS.load(SeqCst) as u64,
+      total: metrics::CONNS_TOTAL.load(Relaxed),
+      rejected: metrics::CONNS_REJECTED.load(Relaxed),
+    },
+    tables,
+    memory: admin.then_some(memory as u64),
+    persistence: Persistence {
+      autosave_interval: conf.autosave_interval,
+      last_save,
+      last_save_duration: metrics::SAVE_DURATION.load(Relaxed) as f64 / 1e6,
+      last_save_size: metrics::SAVE_SIZE.load(Relaxed),
+      healthy,
+    },
+  }
+}
@@ -0,0 +1,262 @@
+use crate::watch::{until, Watcher};
+use crate::{metrics, pubsub, TableValue, DATABASE};
+use protocol::{KeyspaceAction, ListEnd, PrimitiveValue, Status, Status::*};
+
+use scc::hash_map::Entry;
+use std::ops::Range;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+// HELPERS
+
+#[inline]
+fn expired(value: &TableValue) -> bool {
+  value.expiry.is_some_and(|st| st <= SystemTime::now())
+}
+
+#[inline]
+fn as_list(value: &mut TableValue) -> Result<&mut Vec<PrimitiveValue>, Status> {
+  match &mut value.value {
+    PrimitiveValue::Array(list) => Ok(list),
+    _ => Err(WrongType),
+  }
+}
+
+// inclusive `start` and `stop`, negative ones counting back from the end
+fn range(len: usize, start: i64, stop: i64) -> Range<usize> {
+  let len = len as i64;
+  let from_end = |i: i64| if i < 0 { len + i } else { i };
+  let start = from_end(start).clamp(0, len);
+  let end = from_end(stop).saturating_add(1).clamp(start, len);
+  start as usize..end as usize
+}
+
+// pop up to `count` values from `end`, in the order they were popped
+fn take(list: &mut Vec<PrimitiveValue>, end: ListEnd, count: usize) -> Vec<PrimitiveValue> {
+  let count = count.min(list.len());
+  match end {
+    ListEnd::Front => list.drain(..count).collect(),
+    ListEnd::Back => list.drain(list.len() - count..).rev().collect(),
+  }
+}
+
+// keep only the values from `start` to `stop` inclusive
+fn keep(list: &mut Vec<PrimitiveValue>, start: i64, stop: i64) {
+  let keep = range(list.len(), start, stop);
+  list.truncate(keep.end);
+  list.drain(..keep.start);
+}
+
+async fn expire(table: &str, key: &str) {
+  metrics::EXPIRED_KEYS.fetch_add(1, Relaxed);
+  pubsub::notify(KeyspaceAction::Expire, table, Some(key)).await;
+}
+
+// run `op` on the list at `key` while holding its entry, and notify if it returns true
+async fn with_list<R>(
+  table: &str,
+  key: &str,
+  op: impl FnOnce(&mut Vec<PrimitiveValue>) -> (R, bool),
+) -> Result<R, Status> {
+  let tbl = DATABASE.get_async(table).await.ok_or(NoSuchTable)?;
+  let mut entry = tbl.get_async(key).await.ok_or(NoSuchKey)?;
+  if expired(entry.get()) {
+    let _ = entry.remove();
+    drop(tbl);
+    expire(table, key).await;
+    return Err(KeyExpired);
+  }
+  let (res, modified) = op(as_list(entry.get_mut())?);
+  drop(entry);
+  drop(tbl);
+  if modified {
+    pubsub::notify(KeyspaceAction::Update, table, Some(key)).await;
+  }
+  Ok(res)
+}
+
+// OPERATIONS
+
+/// Push `values` in order onto `end` of the list, creating it if needed. Returns the new length.
+pub async fn push(
+  table: &str,
+  key: String,
+  end: ListEnd,
+  values: Vec<PrimitiveValue>,
+) -> Result<u64, Status> {
+  let tbl = DATABASE.get_async(table).await.ok_or(NoSuchTable)?;
+  let (action, length, replaced) = match tbl.entry_async(key.clone()).await {
+    Entry::Occupied(mut e) if !expired(e.get()) => {
+      let list = as_list(e.get_mut())?;
+      match end {
+        ListEnd::Front => drop(list.splice(0..0, values)),
+        ListEnd::Back => list.extend(values),
+      }
+      (KeyspaceAction::Update, list.len(), false)
+    }
+    // an expired key is replaced, as if it had been removed first
+    Entry::Occupied(mut e) => {
+      let length = values.len();
+      *e.get_mut() = TableValue {
+        value: PrimitiveValue::Array(values),
+        expiry: None,
+      };
+      (KeyspaceAction::Insert, length, true)
+    }
+    Entry::Vacant(e) => {
+      let length = values.len();
+      e.insert_entry(TableValue {
+        value: PrimitiveValue::Array(values),
+        expiry: None,
+      });
+      (KeyspaceAction::Insert, length, false)
+    }
+  };
+  drop(tbl);
+  if replaced {
+    expire(table, &key).await;
+  }
+  pubsub::notify(action, table, Some(&key)).await;
+  Ok(length as u64)
+}
+
+/// Pop up to `count` values from `end` of the list, in the order they were popped.
+pub async fn pop(
+  table: &str,
+  key: &str,
+  end: ListEnd,
+  count: usize,
+) -> Result<Vec<PrimitiveValue>, Status> {
+  with_list(table, key, |list| {
+    let popped = take(list, end, count);
+    let modified = !popped.is_empty();
+    (popped, modified)
+  })
+  .await
+}
+
+/// Pop one value from `end` of the list, waiting for one to be pushed if it's empty or missing,
+/// or until `timeout` seconds pass.
+/// Every popper blocked on a key is woken by each push and races for the values, so with more
+/// poppers than values, which ones get them isn't first come first served.
+pub async fn blocking_pop(
+  table: &str,
+  key: &str,
+  end: ListEnd,
+  timeout: Option<u64>,
+) -> Result<PrimitiveValue, Status> {
+  // a timeout too long to represent never ends
+  let timeout = timeout.and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs)));
+  loop {
+    // registered before popping, so a push in between isn't missed
+    let mut watcher = Watcher::new(table.to_owned(), key.to_owned()).await;
+    match pop(table, key, end, 1).await {
+      Ok(mut popped) if !popped.is_empty() => return Ok(popped.remove(0)),
+      Ok(_) | Err(NoSuchKey) | Err(KeyExpired) => {}
+      Err(e) => return Err(e),
+    }
+    tokio::select! {
+      biased;
+      _ = watcher.changed() => {}
+      _ = until(timeout) => return Err(WaitTimedOut),
+    }
+  }
+}
+
+/// The values from `start` to `stop` inclusive, negative indices counting back from the end.
+pub async fn get_range(
+  table: &str,
+  key: &str,
+  start: i64,
+  stop: i64,
+) -> Result<Vec<PrimitiveValue>, Status> {
+  with_list(table, key, |list| {
+    let values = list[range(list.len(), start, stop)].to_vec();
+    (values, false)
+  })
+  .await
+}
+
+/// Keep only the values from `start` to `stop` inclusive, returning the new length.
+pub async fn trim(table: &str, key: &str, start: i64, stop: i64) -> Result<u64, Status> {
+  with_list(table, key, |list| {
+    let len = list.len();
+    keep(list, start, stop);
+    (list.len() as u64, list.len() != len)
+  })
+  .await
+}
+
+pub async fn length(table: &str, key: &str) -> Result<u64, Status> {
+  with_list(table, key, |list| (list.len() as u64, false)).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn list(n: i64) -> Vec<PrimitiveValue> {
+    (0..n).map(PrimitiveValue::Int).collect()
+  }
+
+  #[test]
+  fn range_indices() {
+    assert_eq!(range(5, 0, -1), 0..5);
+    assert_eq!(range(5, 1, 3), 1..4);
+    assert_eq!(range(5, -2, -1), 3..5);
+    assert_eq!(range(5, -3, 3), 2..4);
+    assert_eq!(range(0, 0, -1), 0..0);
+  }
+
+  #[test]
+  fn range_out_of_bounds() {
+    assert_eq!(range(5, 3, 1), 3..3);
+    assert_eq!(range(5, 7, 9), 5..5);
+    assert_eq!(range(5, -9, 1), 0..2);
+    assert_eq!(range(5, 0, -9), 0..0);
+    assert_eq!(range(5, 2, 100), 2..5);
+  }
+
+  #[test]
+  fn range_extremes() {
+    assert_eq!(range(5, i64::MIN, i64::MAX), 0..5);
+    assert_eq!(range(5, i64::MAX, i64::MAX), 5..5);
+    assert_eq!(range(5, i64::MIN, i64::MIN), 0..0);
+    assert_eq!(range(5, 0, i64::MIN), 0..0);
+    assert_eq!(range(5, i64::MAX, i64::MIN), 5..5);
+  }
+
+  #[test]
+  fn take_clamps_count() {
+    let mut l = list(3);
+    assert_eq!(take(&mut l, ListEnd::Front, 2), list(2));
+    assert_eq!(l, vec![PrimitiveValue::Int(2)]);
+    let mut l = list(3);
+    assert_eq!(
+      take(&mut l, ListEnd::Back, usize::MAX),
+      list(3).into_iter().rev().collect::<Vec<_>>()
+    );
+    assert!(l.is_empty());
+    assert!(take(&mut l, ListEnd::Front, 1).is_empty());
+    let mut l = list(3);
+    assert!(take(&mut l, ListEnd::Back, 0).is_empty());
+    assert_eq!(l, list(3));
+  }
+
+  #[test]
+  fn keep_trims() {
+    let mut l = list(5);
+    keep(&mut l, 1, -2);
+    assert_eq!(l, list(4)[1..]);
+    let mut l = list(5);
+    keep(&mut l, i64::MIN, i64::MAX);
+    assert_eq!(l, list(5));
+    let mut l = list(5);
+    keep(&mut l, 3, 1);
+    assert!(l.is_empty());
+    let mut l = list(5);
+    keep(&mut l, -1, i64::MAX);
+    assert_eq!(l, vec![PrimitiveValue::Int(4)]);
+  }
+}
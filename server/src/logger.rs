@@ -1,22 +1,169 @@
-pub use log::{debug, error, info, log, trace, warn, Level};
+use crate::config::{LogConfig, LogFormat};
+pub use tracing::{debug, error, info, trace, warn, Level};
+
+use std::future::{poll_fn, Future};
+use std::io::{stderr, IsTerminal};
+use std::pin::pin;
+use std::sync::{Mutex, OnceLock};
+use tracing::level_filters::LevelFilter;
+use tracing::Span;
+use tracing_subscriber::layer::{Layer, Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
 
 // based on log crate error! impl
 #[macro_export]
 macro_rules! fatal {
-    // fatal!(target: "my_target", key1 = 42, key2 = true; "a {} event", "log")
+    // fatal!(target: "my_target", key1 = 42, key2 = true, "a {} event", "log")
     // fatal!(target: "my_target", "a {} event", "log")
     (target: $target:expr, $($arg:tt)+) => ({
-        log::log!(target: $target, log::Level::Error, $($arg)+);
+        tracing::error!(target: $target, $($arg)+);
         $crate::save();
         std::process::exit(1);
     });
 
     // fatal!("a {} event", "log")
     ($($arg:tt)+) => ({
-        log::log!(log::Level::Error, $($arg)+);
+        tracing::error!($($arg)+);
         $crate::save();
         std::process::exit(1);
     })
 }
 
 pub use fatal;
+
+// SUBSCRIBER
+
+// both layers are swapped out once the config is loaded
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type Output = Box<dyn Layer<Filtered> + Send + Sync>;
+
+struct Handles {
+  filter: reload::Handle<EnvFilter, Registry>,
+  output: reload::Handle<Output, Filtered>,
+}
+
+// not a `Global`, since the output layer isn't `Debug`
+static HANDLES: OnceLock<Handles> = OnceLock::new();
+
+fn filter(level: &str) -> Result<EnvFilter, String> {
+  match std::env::var("RUST_LOG") {
+    Ok(env) => EnvFilter::try_new(&env).map_err(|e| format!("RUST_LOG: {e}")),
+    Err(_) => EnvFilter::try_new(level).map_err(|e| e.to_string()),
+  }
+}
+
+fn output(format: LogFormat) -> Output {
+  let layer = fmt::layer()
+    .with_writer(stderr)
+    .with_ansi(stderr().is_terminal());
+  match format {
+    LogFormat::Text => layer.boxed(),
+    LogFormat::Json => layer
+      .json()
+      .with_current_span(false)
+      .with_span_list(true)
+      .boxed(),
+  }
+}
+
+// dependencies still use `log`, skip their records below the enabled level without asking the subscriber
+fn sync_log_level() {
+  log::set_max_level(match LevelFilter::current().into_level() {
+    None => log::LevelFilter::Off,
+    Some(tracing::Level::ERROR) => log::LevelFilter::Error,
+    Some(tracing::Level::WARN) => log::LevelFilter::Warn,
+    Some(tracing::Level::INFO) => log::LevelFilter::Info,
+    Some(tracing::Level::DEBUG) => log::LevelFilter::Debug,
+    Some(tracing::Level::TRACE) => log::LevelFilter::Trace,
+  });
+}
+
+/// Log plain text at `info` (or `RUST_LOG`) until the config is loaded.
+pub fn init() {
+  let filter = filter("info").unwrap_or_else(|e| {
+    eprintln!("Invalid log filter, using `info`: {e}");
+    EnvFilter::new("info")
+  });
+  let (filter, filter_handle) = reload::Layer::new(filter);
+  let (output, output_handle) = reload::Layer::new(output(LogFormat::Text));
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(output)
+    .init();
+
+  let _ = HANDLES.set(Handles {
+    filter: filter_handle,
+    output: output_handle,
+  });
+}
+
+/// Switch to the configured level and format.
+pub fn configure(conf: &LogConfig) {
+  let Some(handles) = HANDLES.get() else {
+    return;
+  };
+  match filter(&conf.level) {
+    Ok(filter) => {
+      if let Err(e) = handles.filter.reload(filter) {
+        error!("Failed to set log level: {}", e);
+      }
+    }
+    Err(e) => warn!("Invalid log level `{}`, ignoring: {}", conf.level, e),
+  }
+  if let Err(e) = handles.output.reload(output(conf.format)) {
+    error!("Failed to set log format: {}", e);
+  }
+  sync_log_level();
+}
+
+// SPANS
+
+#[inline]
+fn enter(span: &Span) {
+  span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+}
+
+#[inline]
+fn exit(span: &Span) {
+  span.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+}
+
+/// A span that can be replaced while the future it instruments is running,
+/// so each request on a connection is logged in its own span.
+#[derive(Debug)]
+pub struct SpanSlot(Mutex<Span>);
+
+// exits the slot's current span, even if polling panics
+struct Exit<'a>(&'a SpanSlot);
+
+impl Drop for Exit<'_> {
+  fn drop(&mut self) {
+    exit(&self.0 .0.lock().unwrap());
+  }
+}
+
+impl SpanSlot {
+  pub fn new(span: Span) -> Self {
+    Self(Mutex::new(span))
+  }
+
+  /// Switch to `span` immediately. Only call this from the future being instrumented.
+  pub fn set(&self, span: Span) {
+    let mut current = self.0.lock().unwrap();
+    exit(&current);
+    enter(&span);
+    *current = span;
+  }
+
+  /// Run `fut`, entering whichever span is in the slot while it's polled.
+  pub async fn instrument<F: Future>(&self, fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    poll_fn(|cx| {
+      enter(&self.0.lock().unwrap());
+      let _exit = Exit(self);
+      fut.as_mut().poll(cx)
+    })
+    .await
+  }
+}
@@ -1,8 +1,22 @@
+pub mod audit;
+pub mod auth;
+pub mod bench;
+pub mod clients;
 pub mod compression;
 pub mod config;
 pub mod connection;
+pub mod expiry;
+pub mod info;
+pub mod list;
 pub mod logger;
+pub mod metrics;
+pub mod monitor;
+pub mod pubsub;
+pub mod ratelimit;
 pub mod server;
+pub mod slowlog;
+pub mod tls;
+pub mod watch;
 
 mod util;
 pub use util::*;
@@ -11,16 +25,21 @@ use config::*;
 use logger::*;
 use protocol::*;
 
-use rmp_serde::{from_slice, to_vec};
+use compression::{read::reader_sync, write::StreamWriter, Level, Mode};
+use rmp_serde::{decode::from_read, encode::write as write_msgpack};
 use toml::{from_str, to_string_pretty};
 
 use getargs::{Opt, Options};
 use scc::HashMap;
 use tokio::time::{sleep, Duration};
 
-use std::fs::{canonicalize as resolve, create_dir_all, write, File};
-use std::io::{ErrorKind as IoErrorKind, Read};
+use std::cell::Cell;
+use std::fs::{canonicalize as resolve, create_dir_all, rename, write, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind as IoErrorKind, IsTerminal, Read, Write};
 use std::path::PathBuf;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // USE JEMALLOC
 
@@ -37,23 +56,109 @@ pub type Database = HashMap<String, Table, Hasher>;
 
 pub static DB_PATH: Global<PathBuf> = Global::new();
 pub static DATABASE: Global<Database> = Global::new();
+pub static STARTED: Global<Instant> = Global::new();
+
+// compressed snapshots start with this, followed by the compression mode
+// raw MessagePack snapshots always start with a map marker, so they can't collide
+const SNAPSHOT_MAGIC: &[u8; 4] = b"VOID";
+
+// serializes saves, which would otherwise write the same temporary file
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+  // `fatal!` saves before exiting, so don't save again if saving is what failed
+  static SAVING: Cell<bool> = const { Cell::new(false) };
+}
 
 fn save() {
   if let Some(db) = DATABASE.get() {
-    let bytes = wrap_fatal!(to_vec(db), "Failed to serialize database: {}");
-    wrap_fatal!(write(&*DB_PATH, bytes), "Failed to write database: {}");
+    if SAVING.replace(true) {
+      return;
+    }
+    let _lock = SAVE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let start = Instant::now();
+
+    // written beside the snapshot and renamed over it, so a failed save keeps the last good one
+    let mut tmp = DB_PATH.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let file = wrap_fatal!(File::create(&tmp), "Failed to open database: {}");
+    let mut file = BufWriter::new(file);
+    let mode = CONFIG.get().and_then(|c| c.save_compression);
+
+    if let Some(mode) = mode {
+      let header = [SNAPSHOT_MAGIC.as_slice(), &[mode as u8]].concat();
+      wrap_fatal!(file.write_all(&header), "Failed to write database: {}");
+      let mut writer = wrap_fatal!(
+        StreamWriter::new(file, mode, Level::default()),
+        "Failed to compress database: {}"
+      );
+      wrap_fatal!(
+        write_msgpack(&mut writer, db),
+        "Failed to serialize database: {}"
+      );
+      file = wrap_fatal!(writer.finish(), "Failed to compress database: {}");
+    } else {
+      wrap_fatal!(
+        write_msgpack(&mut file, db),
+        "Failed to serialize database: {}"
+      );
+    }
+
+    let file = wrap_fatal!(
+      file.into_inner().map_err(|e| e.into_error()),
+      "Failed to write database: {}"
+    );
+    wrap_fatal!(file.sync_all(), "Failed to write database: {}");
+    let size = file.metadata().map_or(0, |m| m.len());
+    drop(file);
+    wrap_fatal!(rename(&tmp, &*DB_PATH), "Failed to replace database: {}");
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = DB_PATH.parent().filter(|d| !d.as_os_str().is_empty()) {
+      let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    metrics::SAVES.fetch_add(1, Relaxed);
+    metrics::SAVE_TIME.store(now.as_secs(), Relaxed);
+    metrics::SAVE_DURATION.store(start.elapsed().as_micros() as u64, Relaxed);
+    metrics::SAVE_SIZE.store(size, Relaxed);
+    SAVING.set(false);
+  }
+}
+
+fn load(file: File) -> Database {
+  let mut file = BufReader::new(file);
+  let header = wrap_fatal!(file.fill_buf(), "Failed to read database: {}");
+
+  if header.starts_with(SNAPSHOT_MAGIC) {
+    let mode = header
+      .get(SNAPSHOT_MAGIC.len())
+      .copied()
+      .unwrap_or_default();
+    let mode = wrap_fatal!(
+      Mode::try_from(mode),
+      "Invalid database compression mode: {}"
+    );
+    info!("Database is compressed with {:?}", mode);
+    file.consume(SNAPSHOT_MAGIC.len() + 1);
+    let reader = wrap_fatal!(reader_sync(file, mode), "Failed to decompress database: {}");
+    wrap_fatal!(from_read(reader), "Failed to parse database: {}")
+  } else {
+    wrap_fatal!(from_read(file), "Failed to parse database: {}")
   }
 }
 
 #[tokio::main]
 async fn main() {
-  // initialize logger (info level by default)
+  STARTED.set(Instant::now());
 
-  if std::env::var("RUST_LOG").is_err() {
-    std::env::set_var("RUST_LOG", "info");
-  }
+  // initialize logger (info level until the config is loaded)
 
-  env_logger::init();
+  logger::init();
 
   // load command-line args
 
@@ -71,12 +176,16 @@ async fn main() {
     match opt {
       Opt::Short('h') | Opt::Long("help") => {
         eprintln!(
-          r"Usage: void [OPTIONS]...
+          r"Usage: void [OPTIONS]... [COMMAND]
 In memory key-value fault tolerant cache built to handle millions of requests.
 
   -h, --help       display this help and exit
   -c, --config     specify config.toml path (default: {})
-  -d, --database   specify db.void path (default: {})",
+  -d, --database   specify db.void path (default: {})
+
+Commands:
+  bench-compression [FILE]   benchmark every compression mode against FILE (default: database)
+  hash-password              hash a password read from stdin for use in config.toml",
           conf_path.to_string_lossy(),
           db_path.to_string_lossy()
         );
@@ -99,6 +208,41 @@ In memory key-value fault tolerant cache built to handle millions of requests.
     }
   }
 
+  // run subcommands
+
+  match opts.next_positional() {
+    Some("bench-compression") => {
+      let path = opts.next_positional().map_or(db_path, PathBuf::from);
+      bench::compression(&path).await;
+      return;
+    }
+    Some("hash-password") => {
+      // never from the command line, where it would end up in shell history and `ps`
+      if opts.next_positional().is_some() {
+        fatal!("hash-password reads the password from stdin, not its arguments");
+      }
+      if std::io::stdin().is_terminal() {
+        eprint!("Password: ");
+      }
+      let mut line = String::new();
+      wrap_fatal!(
+        std::io::stdin().read_line(&mut line),
+        "Failed to read password: {}"
+      );
+      let password = line.trim_end_matches(['\r', '\n']);
+      if password.is_empty() {
+        fatal!("Refusing to hash an empty password");
+      }
+      println!(
+        "{}",
+        wrap_fatal!(auth::hash_password(password), "Failed to hash password: {}")
+      );
+      return;
+    }
+    Some(c) => fatal!("Invalid command: {}", c),
+    None => {}
+  }
+
   DB_PATH.set(db_path); // set globally so autosaver can access
 
   // load config
@@ -111,7 +255,9 @@ In memory key-value fault tolerant cache built to handle millions of requests.
       info!("Loading config from {}...", conf_path.to_string_lossy());
       let str = &mut String::new();
       wrap_fatal!(file.read_to_string(str), "Failed to read config: {}");
-      CONFIG.set(wrap_fatal!(from_str(str), "Failed to parse config: {}"));
+      let mut conf: Config = wrap_fatal!(from_str(str), "Failed to parse config: {}");
+      conf.load_users();
+      CONFIG.set(conf);
     }
     Err(e) if e.kind() == IoErrorKind::NotFound => {
       info!("Config not found, creating...");
@@ -125,17 +271,17 @@ In memory key-value fault tolerant cache built to handle millions of requests.
     Err(e) => fatal!("Failed to load config: {}", e),
   }
 
+  logger::configure(&CONFIG.log);
+
   // load database
 
   match File::open(&*DB_PATH) {
-    Ok(mut file) => {
+    Ok(file) => {
       if !file.metadata().is_ok_and(|m| m.is_file()) {
         fatal!("{} is not a file!", DB_PATH.to_string_lossy());
       }
       info!("Loading database from {}...", DB_PATH.to_string_lossy());
-      let buf = &mut Vec::new();
-      wrap_fatal!(file.read_to_end(buf), "Failed to read database: {}");
-      DATABASE.set(wrap_fatal!(from_slice(buf), "Failed to parse database: {}"));
+      DATABASE.set(load(file));
     }
     Err(e) if e.kind() == IoErrorKind::NotFound => {
       info!("Database not found, creating...");
@@ -150,7 +296,15 @@ In memory key-value fault tolerant cache built to handle millions of requests.
 
   // spawn listeners & autosaver
 
+  if let Some(conf) = &CONFIG.audit {
+    audit::init(conf).await;
+  }
+  if let Some(addr) = &CONFIG.metrics {
+    tokio::spawn(metrics::listen(addr));
+  }
+  expiry::track_loaded().await;
   tokio::spawn(server::listen());
+  tokio::spawn(expiry::sweep());
   tokio::spawn(async {
     let duration = Duration::from_secs(CONFIG.autosave_interval);
     loop {
@@ -167,6 +321,8 @@ In memory key-value fault tolerant cache built to handle millions of requests.
     Ok(()) => {
       info!("SIGINT detected, saving...");
       save();
+      #[cfg(unix)]
+      server::remove_sockets();
     }
     Err(e) => error!("Failed to listen for shutdown signal: {}", e),
   }
@@ -0,0 +1,362 @@
+use crate::compression::Mode;
+use crate::connection::CURRENT_CONNS;
+use crate::{auth, logger::*, wrap_fatal, DATABASE};
+use protocol::{Request, Status};
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed, Ordering::SeqCst};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+// COUNTERS
+
+pub static CONNS_TOTAL: AtomicU64 = AtomicU64::new(0);
+pub static CONNS_REJECTED: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_IN: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+pub static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0);
+pub static MESSAGES_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// last successful save
+pub static SAVES: AtomicU64 = AtomicU64::new(0);
+pub static SAVE_TIME: AtomicU64 = AtomicU64::new(0); // seconds since the Unix epoch
+pub static SAVE_DURATION: AtomicU64 = AtomicU64::new(0); // microseconds
+pub static SAVE_SIZE: AtomicU64 = AtomicU64::new(0);
+
+// (compressed, uncompressed) request bytes, indexed by mode bit
+static COMPRESSION: [(AtomicU64, AtomicU64); Mode::ALL.len()] =
+  [const { (AtomicU64::new(0), AtomicU64::new(0)) }; Mode::ALL.len()];
+
+#[inline]
+fn compression(mode: Mode) -> &'static (AtomicU64, AtomicU64) {
+  &COMPRESSION[(mode as u8).trailing_zeros() as usize]
+}
+
+#[inline]
+pub fn compressed(mode: Mode, len: usize, full_len: usize) {
+  let (compressed, uncompressed) = compression(mode);
+  compressed.fetch_add(len as u64, Relaxed);
+  uncompressed.fetch_add(full_len as u64, Relaxed);
+}
+
+// REQUESTS
+
+// upper bounds in seconds
+const BUCKETS: [f64; 12] = [
+  0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+struct Histogram {
+  buckets: [AtomicU64; BUCKETS.len()],
+  sum: AtomicU64, // nanoseconds
+  count: AtomicU64,
+}
+
+const ACTIONS: usize = Request::ACTIONS.len();
+
+// requests by action id and status id
+static REQUESTS: [[AtomicU64; Status::ALL.len()]; ACTIONS] =
+  [const { [const { AtomicU64::new(0) }; Status::ALL.len()] }; ACTIONS];
+// by action id
+static LATENCY: [Histogram; ACTIONS] = [const {
+  Histogram {
+    buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+    sum: AtomicU64::new(0),
+    count: AtomicU64::new(0),
+  }
+}; ACTIONS];
+
+/// Record a handled request, its response status and how long it took, if that's meaningful.
+pub fn request(action: usize, status: Status, took: Option<Duration>) {
+  REQUESTS[action][status.id()].fetch_add(1, Relaxed);
+
+  let Some(took) = took else {
+    return;
+  };
+  let secs = took.as_secs_f64();
+  let hist = &LATENCY[action];
+  if let Some(i) = BUCKETS.iter().position(|b| secs <= *b) {
+    hist.buckets[i].fetch_add(1, Relaxed);
+  }
+  hist.sum.fetch_add(took.as_nanos() as u64, Relaxed);
+  hist.count.fetch_add(1, Relaxed);
+}
+
+// RENDERING
+
+macro_rules! metric {
+  ($out:ident, $name:literal, $kind:literal, $help:literal) => {
+    let _ = writeln!($out, concat!("# HELP ", $name, " ", $help));
+    let _ = writeln!($out, concat!("# TYPE ", $name, " ", $kind));
+  };
+  ($out:ident, $name:literal, $kind:literal, $help:literal, $value:expr) => {
+    metric!($out, $name, $kind, $help);
+    let _ = writeln!($out, concat!($name, " {}"), $value);
+  };
+}
+
+/// Render all metrics in the Prometheus text format.
+pub async fn render() -> String {
+  let mut out = String::with_capacity(8 * 1024);
+
+  // CONNECTIONS
+
+  metric!(
+    out,
+    "void_connections",
+    "gauge",
+    "Open connections.",
+    CURRENT_CONNS.load(SeqCst)
+  );
+  metric!(
+    out,
+    "void_connections_total",
+    "counter",
+    "Accepted connections.",
+    CONNS_TOTAL.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_connections_rejected_total",
+    "counter",
+    "Connections refused by limits or missing client certificates.",
+    CONNS_REJECTED.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_auth_lockouts_total",
+    "counter",
+    "IPs locked out after failed AUTH attempts.",
+    auth::LOCKOUTS.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_received_bytes_total",
+    "counter",
+    "Request bytes received, before stream compression.",
+    BYTES_IN.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_sent_bytes_total",
+    "counter",
+    "Response bytes sent, before stream compression.",
+    BYTES_OUT.load(Relaxed)
+  );
+
+  // REQUESTS
+
+  metric!(
+    out,
+    "void_requests_total",
+    "counter",
+    "Handled requests by action and response status."
+  );
+  for (action, statuses) in Request::ACTIONS.iter().zip(&REQUESTS) {
+    for (status, n) in Status::ALL.iter().zip(statuses) {
+      let n = n.load(Relaxed);
+      if n > 0 {
+        let _ = writeln!(
+          out,
+          "void_requests_total{{action=\"{action}\",status=\"{status}\"}} {n}"
+        );
+      }
+    }
+  }
+
+  metric!(
+    out,
+    "void_request_duration_seconds",
+    "histogram",
+    "Time taken to handle requests by action."
+  );
+  for (action, hist) in Request::ACTIONS.iter().zip(&LATENCY) {
+    let count = hist.count.load(Relaxed);
+    if count == 0 {
+      continue;
+    }
+    let mut total = 0;
+    for (bound, n) in BUCKETS.iter().zip(&hist.buckets) {
+      total += n.load(Relaxed);
+      let _ = writeln!(
+        out,
+        "void_request_duration_seconds_bucket{{action=\"{action}\",le=\"{bound}\"}} {total}"
+      );
+    }
+    let _ = writeln!(
+      out,
+      "void_request_duration_seconds_bucket{{action=\"{action}\",le=\"+Inf\"}} {count}"
+    );
+    let sum = Duration::from_nanos(hist.sum.load(Relaxed)).as_secs_f64();
+    let _ = writeln!(
+      out,
+      "void_request_duration_seconds_sum{{action=\"{action}\"}} {sum}"
+    );
+    let _ = writeln!(
+      out,
+      "void_request_duration_seconds_count{{action=\"{action}\"}} {count}"
+    );
+  }
+
+  // PUB/SUB
+
+  metric!(
+    out,
+    "void_pubsub_messages_dropped_total",
+    "counter",
+    "Messages dropped or subscribers disconnected because a subscriber's queue was full.",
+    MESSAGES_DROPPED.load(Relaxed)
+  );
+
+  // COMPRESSION
+
+  metric!(
+    out,
+    "void_compression_ratio",
+    "gauge",
+    "Uncompressed to compressed size of compressed requests by mode."
+  );
+  for mode in Mode::ALL {
+    let (compressed, uncompressed) = compression(mode);
+    let (compressed, uncompressed) = (compressed.load(Relaxed), uncompressed.load(Relaxed));
+    if compressed > 0 {
+      let ratio = uncompressed as f64 / compressed as f64;
+      let mode = format!("{mode:?}").to_lowercase();
+      let _ = writeln!(out, "void_compression_ratio{{mode=\"{mode}\"}} {ratio}");
+    }
+  }
+
+  // DATABASE
+
+  let mut keys = 0;
+  DATABASE.scan_async(|_, table| keys += table.len()).await;
+  metric!(
+    out,
+    "void_tables",
+    "gauge",
+    "Tables in the database.",
+    DATABASE.len()
+  );
+  metric!(
+    out,
+    "void_keys",
+    "gauge",
+    "Keys in all tables, including expired keys not yet reclaimed.",
+    keys
+  );
+  metric!(
+    out,
+    "void_expired_keys_total",
+    "counter",
+    "Expired keys reclaimed.",
+    EXPIRED_KEYS.load(Relaxed)
+  );
+
+  // PERSISTENCE
+
+  metric!(
+    out,
+    "void_saves_total",
+    "counter",
+    "Successful snapshots.",
+    SAVES.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_save_timestamp_seconds",
+    "gauge",
+    "Time of the last snapshot.",
+    SAVE_TIME.load(Relaxed)
+  );
+  metric!(
+    out,
+    "void_save_duration_seconds",
+    "gauge",
+    "Time taken by the last snapshot.",
+    SAVE_DURATION.load(Relaxed) as f64 / 1e6
+  );
+  metric!(
+    out,
+    "void_save_size_bytes",
+    "gauge",
+    "Size of the last snapshot on disk.",
+    SAVE_SIZE.load(Relaxed)
+  );
+
+  out
+}
+
+// HTTP ENDPOINT
+
+async fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+  // only the request line matters, the rest of the request is ignored
+  let mut buf = [0; 1024];
+  let len = timeout(Duration::from_secs(5), stream.read(&mut buf)).await??;
+  let req = String::from_utf8_lossy(&buf[..len]);
+  let path = req.split_whitespace().nth(1).unwrap_or_default();
+
+  let (status, body) = if req.starts_with("GET ") && (path == "/metrics" || path == "/") {
+    ("200 OK", render().await)
+  } else {
+    ("404 Not Found", String::new())
+  };
+
+  let head = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    body.len()
+  );
+  stream.write_all(head.as_bytes()).await?;
+  stream.write_all(body.as_bytes()).await?;
+  stream.shutdown().await
+}
+
+/// Serve metrics over HTTP at `addr`.
+pub async fn listen(addr: &str) {
+  let listener = wrap_fatal!(TcpListener::bind(addr).await, "Failed to bind metrics: {}");
+  info!("Serving metrics at http://{}/metrics", addr);
+
+  loop {
+    match listener.accept().await {
+      Ok((stream, _)) => {
+        tokio::spawn(async {
+          if let Err(e) = respond(stream).await {
+            debug!("Metrics request failed: {}", e);
+          }
+        });
+      }
+      Err(e) => error!("Metrics connection failed: {}", e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::{from_value, json};
+
+  #[test]
+  fn action_ids_match_tags() {
+    for (id, action) in Request::ACTIONS.into_iter().enumerate() {
+      // every field any request needs, the rest are ignored
+      let req: Request = from_value(json!({
+        "action": action, "username": "u", "password": "p", "mode": 1, "count": 1,
+        "table": "t", "key": "k", "id": 1, "peer": "p", "user": "u", "actions": null,
+        "channels": ["c"], "patterns": null, "channel": "c", "data": 1, "contents": null,
+        "value": 1, "lifetime": null, "timeout": null, "end": "FRONT", "values": [1],
+        "start": 0, "stop": -1,
+      }))
+      .unwrap();
+      assert_eq!(req.action_id(), id);
+      assert_eq!(req.action(), action);
+    }
+  }
+
+  #[test]
+  fn statuses_are_indexed() {
+    for (id, status) in Status::ALL.into_iter().enumerate() {
+      assert_eq!(status.id(), id);
+    }
+  }
+}
@@ -0,0 +1,85 @@
+use crate::auth::User;
+use crate::connection::Peer;
+use crate::{glob_match, logger::*};
+use protocol::{MonitorEvent, Request};
+
+use std::future::pending;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{channel, error::RecvError, Receiver, Sender};
+
+// EVENTS
+
+// events a monitor can fall behind by before it starts skipping them
+const BACKLOG: usize = 1024;
+
+static EVENTS: LazyLock<Sender<MonitorEvent>> = LazyLock::new(|| channel(BACKLOG).0);
+// checked first, so requests cost nothing extra while nobody is monitoring
+static MONITORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Send `req` to all monitors.
+#[inline]
+pub fn publish(req: &Request, peer: Peer, user: Option<User>) {
+  if MONITORS.load(Relaxed) == 0 {
+    return;
+  }
+  let time = SystemTime::now().duration_since(UNIX_EPOCH);
+  let _ = EVENTS.send(MonitorEvent {
+    time: time.unwrap_or_default().as_secs_f64(),
+    peer: peer.to_string(),
+    user: user.map(|u| u.name.to_owned()),
+    action: req.action().to_owned(),
+    table: req.table().map(str::to_owned),
+    key: req.key().map(str::to_owned),
+  });
+}
+
+// MONITOR
+
+/// A connection's subscription to processed requests.
+pub struct Monitor {
+  rx: Receiver<MonitorEvent>,
+  table: Option<String>,
+  actions: Option<Vec<String>>,
+}
+
+impl Monitor {
+  pub fn new(table: Option<String>, actions: Option<Vec<String>>) -> Self {
+    MONITORS.fetch_add(1, Relaxed);
+    Self {
+      rx: EVENTS.subscribe(),
+      table,
+      actions,
+    }
+  }
+
+  fn matches(&self, event: &MonitorEvent) -> bool {
+    let table = match (&self.table, &event.table) {
+      (None, _) => true,
+      (Some(pattern), Some(table)) => glob_match(pattern, table),
+      (Some(_), None) => false,
+    };
+    let action = self.actions.as_ref();
+    table && action.is_none_or(|a| a.iter().any(|a| a.eq_ignore_ascii_case(&event.action)))
+  }
+
+  /// Wait for the next matching event. Cancel safe.
+  pub async fn recv(&mut self) -> MonitorEvent {
+    loop {
+      match self.rx.recv().await {
+        Ok(event) if self.matches(&event) => return event,
+        Ok(_) => {}
+        Err(RecvError::Lagged(n)) => warn!("Monitor fell behind, skipped {} requests", n),
+        // the sender is static and never dropped
+        Err(RecvError::Closed) => pending().await,
+      }
+    }
+  }
+}
+
+impl Drop for Monitor {
+  fn drop(&mut self) {
+    MONITORS.fetch_sub(1, Relaxed);
+  }
+}
@@ -0,0 +1,295 @@
+use crate::auth::User;
+use crate::config::{Role, SlowConsumer, CONFIG};
+use crate::{clients, glob_match, logger::*, metrics, watch};
+use protocol::{KeyspaceAction, KeyspaceEvent, Message, Payload, PrimitiveValue};
+
+use scc::{hash_map::Entry, HashMap};
+use std::collections::HashSet;
+use std::future::pending;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::LazyLock;
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+
+// SUBSCRIPTIONS
+
+// connection id and its queue of pushed messages and events
+type Subscribers = Vec<(u64, Sender<Payload>)>;
+
+// exact channel -> subscribers
+static CHANNELS: LazyLock<HashMap<String, Subscribers>> = LazyLock::new(HashMap::default);
+// pattern -> subscribers, every pattern is checked on publish
+static PATTERNS: LazyLock<HashMap<String, Subscribers>> = LazyLock::new(HashMap::default);
+
+async fn add(map: &HashMap<String, Subscribers>, key: String, id: u64, tx: &Sender<Payload>) {
+  let mut entry = map.entry_async(key).await.or_default();
+  entry.get_mut().push((id, tx.clone()));
+}
+
+fn remove(map: &HashMap<String, Subscribers>, key: &str, id: u64) {
+  map.update(key, |_, subs| subs.retain(|(i, _)| *i != id));
+  map.remove_if(key, |subs| subs.is_empty());
+}
+
+// PUBLISHING
+
+// queue `payload` for one subscriber, applying the slow consumer policy if it's full
+fn push(id: u64, tx: &Sender<Payload>, payload: Payload) -> bool {
+  match tx.try_send(payload) {
+    Ok(()) => true,
+    Err(TrySendError::Full(_)) => {
+      metrics::MESSAGES_DROPPED.fetch_add(1, Relaxed);
+      match CONFIG.pubsub.slow_consumer {
+        SlowConsumer::Drop => debug!("Subscriber {} is full, dropped a message", id),
+        SlowConsumer::Disconnect => {
+          warn!("Subscriber {} is full, disconnecting", id);
+          clients::kill_id(id);
+        }
+      }
+      false
+    }
+    // the connection is closing
+    Err(TrySendError::Closed(_)) => false,
+  }
+}
+
+/// Queue a message for every subscriber of `channel`, returning how many it was queued for.
+pub async fn publish(channel: &str, data: PrimitiveValue) -> u64 {
+  let mut receivers = 0;
+  let message = |pattern: Option<&String>| Payload::Message {
+    message: Message {
+      channel: channel.to_owned(),
+      pattern: pattern.cloned(),
+      data: data.clone(),
+    },
+  };
+
+  CHANNELS
+    .read_async(channel, |_, subs| {
+      for (id, tx) in subs {
+        receivers += push(*id, tx, message(None)) as u64;
+      }
+    })
+    .await;
+  PATTERNS
+    .scan_async(|pattern, subs| {
+      if glob_match(pattern, channel) {
+        for (id, tx) in subs {
+          receivers += push(*id, tx, message(Some(pattern))) as u64;
+        }
+      }
+    })
+    .await;
+
+  receivers
+}
+
+// KEYSPACE NOTIFICATIONS
+
+// (table, key) glob patterns, key events match any key if the key pattern is `None`
+type KeyPattern = (String, Option<String>);
+
+struct Keyspace {
+  tx: Sender<Payload>,
+  // events are only sent for tables this user can read
+  user: Option<User>,
+  patterns: Vec<KeyPattern>,
+}
+
+impl Keyspace {
+  fn matches(&self, table: &str, key: Option<&str>) -> bool {
+    self.user.is_some_and(|u| u.can(Role::Read, table))
+      && self.patterns.iter().any(|(t, k)| {
+        // table events match every subscription on the table
+        glob_match(t, table) && key.is_none_or(|key| k.as_ref().is_none_or(|k| glob_match(k, key)))
+      })
+  }
+}
+
+// connection id -> keyspace subscriptions
+static KEYSPACE: LazyLock<HashMap<u64, Keyspace>> = LazyLock::new(HashMap::default);
+// checked first, so changes cost nothing extra while nobody is subscribed
+static KEYSPACE_SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether anything is waiting on keyspace changes, through subscriptions or watchers.
+pub fn listening() -> bool {
+  KEYSPACE_SUBSCRIBERS.load(Relaxed) > 0 || watch::watching()
+}
+
+/// Push a change to every connection subscribed to `table` (and `key`), and wake its watchers.
+pub async fn notify(action: KeyspaceAction, table: &str, key: Option<&str>) {
+  watch::changed(action, table, key).await;
+  if KEYSPACE_SUBSCRIBERS.load(Relaxed) == 0 {
+    return;
+  }
+  KEYSPACE
+    .scan_async(|id, ks| {
+      if ks.matches(table, key) {
+        let keyspace = KeyspaceEvent {
+          action,
+          table: table.to_owned(),
+          key: key.map(str::to_owned),
+        };
+        push(*id, &ks.tx, Payload::Keyspace { keyspace });
+      }
+    })
+    .await;
+}
+
+// SUBSCRIBER
+
+/// A connection's subscriptions and message queue, unsubscribed when dropped.
+pub struct Subscriber {
+  id: u64,
+  tx: Sender<Payload>,
+  rx: Receiver<Payload>,
+  channels: HashSet<String>,
+  patterns: HashSet<String>,
+  keyspace: Vec<KeyPattern>,
+}
+
+impl Subscriber {
+  pub fn new(id: u64) -> Self {
+    let (tx, rx) = channel(CONFIG.pubsub.queue_size.max(1));
+    Self {
+      id,
+      tx,
+      rx,
+      channels: HashSet::new(),
+      patterns: HashSet::new(),
+      keyspace: Vec::new(),
+    }
+  }
+
+  /// Subscribe to every given channel and pattern, or none if that would exceed `max_subscriptions`.
+  pub async fn subscribe(&mut self, channels: Vec<String>, patterns: Vec<String>) -> bool {
+    let new_channels = channels.iter().filter(|c| !self.channels.contains(*c));
+    let new_patterns = patterns.iter().filter(|p| !self.patterns.contains(*p));
+    let new =
+      new_channels.collect::<HashSet<_>>().len() + new_patterns.collect::<HashSet<_>>().len();
+    if self.count() as usize + new > CONFIG.pubsub.max_subscriptions {
+      return false;
+    }
+
+    for channel in channels {
+      if self.channels.insert(channel.clone()) {
+        add(&CHANNELS, channel, self.id, &self.tx).await;
+      }
+    }
+    for pattern in patterns {
+      if self.patterns.insert(pattern.clone()) {
+        add(&PATTERNS, pattern, self.id, &self.tx).await;
+      }
+    }
+    true
+  }
+
+  /// Unsubscribe from the given channels and patterns, or everything if both are `None`.
+  pub fn unsubscribe(&mut self, channels: Option<Vec<String>>, patterns: Option<Vec<String>>) {
+    if channels.is_none() && patterns.is_none() {
+      self.clear();
+      return;
+    }
+    for channel in channels.unwrap_or_default() {
+      if self.channels.remove(&channel) {
+        remove(&CHANNELS, &channel, self.id);
+      }
+    }
+    for pattern in patterns.unwrap_or_default() {
+      if self.patterns.remove(&pattern) {
+        remove(&PATTERNS, &pattern, self.id);
+      }
+    }
+  }
+
+  fn clear(&mut self) {
+    for channel in self.channels.drain() {
+      remove(&CHANNELS, &channel, self.id);
+    }
+    for pattern in self.patterns.drain() {
+      remove(&PATTERNS, &pattern, self.id);
+    }
+  }
+
+  /// Subscribe to changes on tables matching `table` and keys matching `key`, as `user`.
+  /// Fails if that would exceed `max_subscriptions`.
+  pub async fn subscribe_keyspace(
+    &mut self,
+    user: Option<User>,
+    table: String,
+    key: Option<String>,
+  ) -> bool {
+    let pattern = (table, key);
+    if !self.keyspace.contains(&pattern) {
+      if self.count() as usize >= CONFIG.pubsub.max_subscriptions {
+        return false;
+      }
+      self.keyspace.push(pattern);
+    }
+    self.sync_keyspace(user).await;
+    true
+  }
+
+  /// Unsubscribe from exactly `table` and `key`, or every keyspace subscription if `table` is `None`.
+  pub async fn unsubscribe_keyspace(
+    &mut self,
+    user: Option<User>,
+    table: Option<String>,
+    key: Option<String>,
+  ) {
+    match table {
+      Some(table) => self.keyspace.retain(|p| *p != (table.clone(), key.clone())),
+      None => self.keyspace.clear(),
+    }
+    self.sync_keyspace(user).await;
+  }
+
+  // replace this connection's entry in `KEYSPACE`
+  async fn sync_keyspace(&self, user: Option<User>) {
+    if self.keyspace.is_empty() {
+      if KEYSPACE.remove_async(&self.id).await.is_some() {
+        KEYSPACE_SUBSCRIBERS.fetch_sub(1, Relaxed);
+      }
+      return;
+    }
+    let keyspace = Keyspace {
+      tx: self.tx.clone(),
+      user,
+      patterns: self.keyspace.clone(),
+    };
+    match KEYSPACE.entry_async(self.id).await {
+      Entry::Occupied(mut e) => *e.get_mut() = keyspace,
+      Entry::Vacant(e) => {
+        e.insert_entry(keyspace);
+        KEYSPACE_SUBSCRIBERS.fetch_add(1, Relaxed);
+      }
+    }
+  }
+
+  /// Send keyspace events for the tables `user` can read, after the connection authenticated again.
+  pub async fn set_user(&self, user: Option<User>) {
+    self.sync_keyspace(user).await;
+  }
+
+  #[inline]
+  pub fn count(&self) -> u64 {
+    (self.channels.len() + self.patterns.len() + self.keyspace.len()) as u64
+  }
+
+  /// Wait for the next queued message or event. Cancel safe.
+  pub async fn recv(&mut self) -> Payload {
+    match self.rx.recv().await {
+      Some(payload) => payload,
+      // we hold a sender, so the queue never closes
+      None => pending().await,
+    }
+  }
+}
+
+impl Drop for Subscriber {
+  fn drop(&mut self) {
+    self.clear();
+    if KEYSPACE.remove(&self.id).is_some() {
+      KEYSPACE_SUBSCRIBERS.fetch_sub(1, Relaxed);
+    }
+  }
+}
@@ -0,0 +1,286 @@
+use crate::auth::User;
+use crate::config::{RateLimit, CONFIG};
+use crate::connection::Peer;
+use crate::logger::*;
+
+use scc::hash_map::OccupiedEntry;
+use scc::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::LazyLock;
+use tokio::time::{interval, Duration, Instant};
+
+// TOKEN BUCKETS
+
+// at least one, so a request can always pass eventually however low the rate
+#[inline]
+fn capacity(rate: f64, burst: f64) -> f64 {
+  (rate * burst).max(1.0)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+  tokens: f64,
+  last: Instant,
+}
+
+impl Bucket {
+  #[inline]
+  fn full(rate: f64, burst: f64, now: Instant) -> Self {
+    Self {
+      tokens: capacity(rate, burst),
+      last: now,
+    }
+  }
+
+  #[inline]
+  fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+    let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(capacity(rate, burst));
+    self.last = now;
+  }
+}
+
+/// Request and byte buckets for one connection, IP or user.
+#[derive(Debug, Clone, Copy)]
+struct Buckets {
+  requests: Bucket,
+  bytes: Bucket,
+}
+
+impl Buckets {
+  fn new(limit: &RateLimit, now: Instant) -> Self {
+    let requests = limit.requests_per_sec.unwrap_or_default();
+    let bytes = limit.bytes_per_sec.unwrap_or_default();
+    Self {
+      requests: Bucket::full(requests, limit.burst, now),
+      bytes: Bucket::full(bytes, limit.burst, now),
+    }
+  }
+
+  fn refill(&mut self, limit: &RateLimit, now: Instant) {
+    if let Some(rate) = limit.requests_per_sec {
+      self.requests.refill(now, rate, limit.burst);
+    }
+    if let Some(rate) = limit.bytes_per_sec {
+      self.bytes.refill(now, rate, limit.burst);
+    }
+  }
+
+  /// Whether one request and `len` bytes are available, without taking them.
+  fn allows(&self, limit: &RateLimit, len: usize) -> bool {
+    let requests = limit
+      .requests_per_sec
+      .is_none_or(|_| self.requests.tokens >= 1.0);
+    // messages larger than the whole bucket pass once it's full, instead of never
+    let bytes = limit
+      .bytes_per_sec
+      .is_none_or(|rate| self.bytes.tokens >= (len as f64).min(capacity(rate, limit.burst)));
+    requests && bytes
+  }
+
+  #[inline]
+  fn take(&mut self, len: usize) {
+    self.requests.tokens -= 1.0;
+    self.bytes.tokens -= len as f64;
+  }
+
+  // all buckets are full again, so the entry can be dropped
+  fn idle(&self, limit: &RateLimit, now: Instant) -> bool {
+    let mut refilled = *self;
+    refilled.refill(limit, now);
+    let full = |rate: Option<f64>, b: Bucket| {
+      rate.is_none_or(|rate| b.tokens >= capacity(rate, limit.burst))
+    };
+    full(limit.requests_per_sec, refilled.requests) && full(limit.bytes_per_sec, refilled.bytes)
+  }
+}
+
+// SHARED LIMITS
+
+static IPS: LazyLock<HashMap<IpAddr, Buckets>> = LazyLock::new(HashMap::default);
+static USERS: LazyLock<HashMap<&'static str, Buckets>> = LazyLock::new(HashMap::default);
+
+// locked until dropped, so concurrent requests can't both pass on the same tokens
+async fn shared<'a, K>(
+  map: &'a HashMap<K, Buckets>,
+  key: K,
+  limit: &RateLimit,
+) -> OccupiedEntry<'a, K, Buckets>
+where
+  K: Eq + Hash,
+{
+  let entry = map.entry_async(key).await;
+  entry.or_insert_with(|| Buckets::new(limit, Instant::now()))
+}
+
+/// Periodically forget IPs and users that haven't been limited recently.
+pub async fn cleanup() {
+  let limits = &CONFIG.rate_limit;
+  let mut interval = interval(Duration::from_secs(60));
+  loop {
+    interval.tick().await;
+    let now = Instant::now();
+    if let Some(limit) = &limits.ip {
+      IPS.retain_async(|_, b| !b.idle(limit, now)).await;
+    }
+    if let Some(limit) = &limits.user {
+      USERS.retain_async(|_, b| !b.idle(limit, now)).await;
+    }
+  }
+}
+
+// RATE LIMITER
+
+/// Applies the configured per-connection, per-IP and per-user limits to a connection.
+pub struct RateLimiter {
+  peer: Peer,
+  conn: Option<Buckets>,
+}
+
+impl RateLimiter {
+  pub fn new(peer: Peer) -> Self {
+    let conn = CONFIG.rate_limit.connection.as_ref();
+    let conn = conn.map(|limit| Buckets::new(limit, Instant::now()));
+    Self { peer, conn }
+  }
+
+  /// Whether a request of `len` bytes may be handled now.
+  /// Nothing is taken from any bucket unless all of them allow it.
+  pub async fn allow(&mut self, user: Option<User>, len: usize) -> bool {
+    let limits = &CONFIG.rate_limit;
+    let ip = self.peer.ip();
+    let mut ip_entry = match (ip, &limits.ip) {
+      (Some(ip), Some(limit)) => Some((shared(&IPS, ip, limit).await, limit)),
+      _ => None,
+    };
+    let mut user_entry = match (user, &limits.user) {
+      (Some(user), Some(limit)) => Some((shared(&USERS, user.name, limit).await, limit)),
+      _ => None,
+    };
+
+    let mut buckets = [
+      self.conn.as_mut().zip(limits.connection.as_ref()),
+      ip_entry.as_mut().map(|(e, limit)| (e.get_mut(), *limit)),
+      user_entry.as_mut().map(|(e, limit)| (e.get_mut(), *limit)),
+    ];
+
+    let now = Instant::now();
+    for (b, limit) in buckets.iter_mut().flatten() {
+      b.refill(limit, now);
+      if !b.allows(limit, len) {
+        trace!(
+          "Rate limit exceeded | ip: {:?}, user: {:?}",
+          ip,
+          user.map(|u| u.name)
+        );
+        return false;
+      }
+    }
+    for (b, _) in buckets.into_iter().flatten() {
+      b.take(len);
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limit(requests: Option<f64>, bytes: Option<f64>, burst: f64) -> RateLimit {
+    RateLimit {
+      requests_per_sec: requests,
+      bytes_per_sec: bytes,
+      burst,
+    }
+  }
+
+  // take requests of `len` bytes at `now` until one isn't allowed
+  fn drain(b: &mut Buckets, limit: &RateLimit, len: usize, now: Instant) -> usize {
+    let mut taken = 0;
+    b.refill(limit, now);
+    while b.allows(limit, len) && taken < 1000 {
+      b.take(len);
+      taken += 1;
+    }
+    taken
+  }
+
+  #[test]
+  fn burst_then_refill() {
+    let limit = limit(Some(10.0), None, 2.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert_eq!(drain(&mut b, &limit, 0, start), 20);
+    assert_eq!(
+      drain(&mut b, &limit, 0, start + Duration::from_millis(500)),
+      5
+    );
+    // never refilled past the burst
+    assert_eq!(
+      drain(&mut b, &limit, 0, start + Duration::from_secs(60)),
+      20
+    );
+  }
+
+  #[test]
+  fn low_rates_still_allow_a_request() {
+    let limit = limit(Some(0.5), None, 1.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert_eq!(drain(&mut b, &limit, 0, start), 1);
+    assert_eq!(drain(&mut b, &limit, 0, start + Duration::from_secs(1)), 0);
+    assert_eq!(drain(&mut b, &limit, 0, start + Duration::from_secs(2)), 1);
+  }
+
+  #[test]
+  fn bytes_are_limited() {
+    let limit = limit(None, Some(1000.0), 1.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert_eq!(drain(&mut b, &limit, 300, start), 3);
+    assert_eq!(
+      drain(&mut b, &limit, 300, start + Duration::from_millis(200)),
+      1
+    );
+  }
+
+  #[test]
+  fn oversized_messages_pass_when_full() {
+    let limit = limit(None, Some(1000.0), 1.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert_eq!(drain(&mut b, &limit, 5000, start), 1);
+    // the debt is paid off before anything else passes
+    assert_eq!(drain(&mut b, &limit, 1, start + Duration::from_secs(3)), 0);
+    assert_eq!(
+      drain(&mut b, &limit, 5000, start + Duration::from_secs(5)),
+      1
+    );
+  }
+
+  #[test]
+  fn nothing_is_taken_unless_everything_allows() {
+    let limit = limit(Some(10.0), Some(100.0), 1.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert_eq!(drain(&mut b, &limit, 100, start), 1);
+    // refused for bytes, which must not cost a request
+    assert!(!b.allows(&limit, 100));
+    assert_eq!(b.requests.tokens, 9.0);
+    assert_eq!(drain(&mut b, &limit, 0, start), 9);
+  }
+
+  #[test]
+  fn idle_once_refilled() {
+    let limit = limit(Some(1.0), Some(100.0), 2.0);
+    let start = Instant::now();
+    let mut b = Buckets::new(&limit, start);
+    assert!(b.idle(&limit, start));
+    b.refill(&limit, start);
+    b.take(200);
+    assert!(!b.idle(&limit, start + Duration::from_secs(1)));
+    assert!(b.idle(&limit, start + Duration::from_secs(2)));
+  }
+}
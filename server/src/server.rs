@@ -1,105 +1,314 @@
-use crate::{config::CONFIG, connection::*, logger::*, util::Global, wrap_fatal};
+use crate::auth::{self, User};
+use crate::config::{ConnectionConfig, CONFIG};
+use crate::tls::{self, TlsAcceptor};
+use crate::{audit, connection::*, logger::*, metrics, ratelimit, util::Global};
 use protocol::{Response, Status::ConnLimit};
-use std::sync::atomic::Ordering::SeqCst;
-use tokio::net::TcpListener;
-use tokio_native_tls::{native_tls, TlsAcceptor as AsyncTlsAcceptor};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed, Ordering::SeqCst};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
 
 // IMPLEMENTATION HELPERS
 
 // do some small stuff to hand control off to the connection handler
-async fn conn_handoff<S: RawStream>(conn: &mut Connection<S>, accept: bool) {
+async fn conn_handoff<S: RawStream>(
+  conn: &mut Connection<S>,
+  accept: bool,
+  peer: Peer,
+  // and how it was authenticated
+  user: Option<(User, &'static str)>,
+  listener: &'static ConnectionConfig,
+) {
   if !accept {
     warn!("Too many connections {}", fmt_conns());
+    metrics::CONNS_REJECTED.fetch_add(1, Relaxed);
     // ignore error since we don't want this connection anyways
     let _ = conn.send(Response::status(ConnLimit)).await;
     return;
   }
 
-  handle_conn(conn).await;
+  if let Some((user, auth)) = user {
+    audit::connected(peer, user, auth).await;
+  }
+  handle_conn(conn, peer, user.map(|(u, _)| u), listener).await;
+}
+
+// plain or TLS over TCP
+async fn tcp_handoff(
+  stream: TcpStream,
+  addr: SocketAddr,
+  accept: bool,
+  conf: &'static ConnectionConfig,
+) {
+  let peer = Peer::Tcp(addr);
+  let trusted_user = conf.user.as_deref().and_then(User::get);
+  let trusted_user = trusted_user.map(|u| (u, "listener"));
+
+  // unfortunately TcpStream and TlsStream are different types
+  // we can't overwrite `stream` for TLS and convert it generically
+  // convert the stream types separately and store in conn
+  if !conf.tls {
+    conn_handoff(
+      &mut Connection::from(stream),
+      accept,
+      peer,
+      trusted_user,
+      conf,
+    )
+    .await;
+    return;
+  }
+
+  match TLS_ACCEPTOR.accept(stream).await {
+    Ok(s) => {
+      let user = tls::client_user(&s, CONFIG.tls.as_ref().unwrap());
+      if conf.require_client_cert && user.is_none() {
+        warn!("No client certificate from {}, dropping connection", peer);
+        metrics::CONNS_REJECTED.fetch_add(1, Relaxed);
+      } else {
+        let user = user.map(|u| (u, "certificate")).or(trusted_user);
+        conn_handoff(&mut Connection::from(s), accept, peer, user, conf).await;
+      }
+    }
+    Err(e) => error!("Failed to accept TLS handshake: {}", e),
+  }
+}
+
+// a taken connection slot, given back when dropped, even if the connection handler panics
+struct Slot<C: Deref<Target = AtomicUsize>>(C);
+
+impl<C: Deref<Target = AtomicUsize>> Drop for Slot<C> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, SeqCst);
+  }
+}
+
+// take one of `max` slots, atomically so concurrent accepts can't both take the last one
+#[inline]
+fn reserve<C: Deref<Target = AtomicUsize>>(conns: C, max: usize) -> Option<Slot<C>> {
+  conns
+    .fetch_update(SeqCst, SeqCst, |n| (n < max).then_some(n + 1))
+    .ok()?;
+  Some(Slot(conns))
 }
 
 #[macro_export]
 macro_rules! listener {
-  ($addr:expr, $tls:expr, $max_conns:expr) => {
+  ($conf:expr, $bind:expr, $handoff:path) => {
     async move {
-      let listener = match TcpListener::bind($addr).await {
+      let conf: &'static ConnectionConfig = $conf;
+      let listener = match $bind {
         Ok(l) => l,
         Err(e) => {
-          fatal!("Failed to bind: {}", e);
+          fatal!("Failed to bind {}: {}", conf.addr(), e);
         }
       };
 
-      info!("Listening for connections at {}", $addr);
+      info!("Listening for connections at {}", conf.addr());
+
+      // connections on this listener only
+      let conns = Arc::new(AtomicUsize::new(0));
 
       loop {
-        let stream = match listener.accept().await {
+        let (stream, addr) = match listener.accept().await {
           Err(e) => {
             error!("Connection failed: {}", e);
             continue;
           }
-          Ok((s, _)) => s,
+          Ok(s) => s,
         };
 
-        tokio::spawn(async {
-          // unfortunately TcpStream and TlsStream are different types
-          // we can't overwrite `stream` for TLS and convert it generically
-          // convert the two stream types separately and store in conn
-          if $tls {
-            let stream = match TLS_ACCEPTOR.accept(stream).await {
-              Err(e) => {
-                error!("Failed to accept TLS handshake: {}", e);
-                return;
-                //continue;
-              }
-              Ok(s) => s,
-            };
-            conn_handoff(&mut Connection::from(stream), CURRENT_CONNS.load(SeqCst) < $max_conns).await;
-          } else {
-            conn_handoff(&mut Connection::from(stream), CURRENT_CONNS.load(SeqCst) < $max_conns).await;
-          }
+        let conns = conns.clone();
+        tokio::spawn(async move {
+          // held until the connection ends
+          let slots = reserve(&CURRENT_CONNS, CONFIG.max_conns).and_then(|global| {
+            let local = reserve(conns, conf.max_conns.unwrap_or(usize::MAX))?;
+            Some((global, local))
+          });
+          $handoff(stream, addr, slots.is_some(), conf).await;
         });
       }
     }
   };
 }
 
-pub static TLS_ACCEPTOR: Global<AsyncTlsAcceptor> = Global::new();
+// UNIX SOCKETS
+
+#[cfg(unix)]
+pub use unix::remove_sockets;
+
+#[cfg(unix)]
+mod unix {
+  use super::*;
+  use std::fs::{remove_dir, remove_file, rename, set_permissions, DirBuilder, Permissions};
+  use std::os::unix::fs::{chown, DirBuilderExt, FileTypeExt, PermissionsExt};
+  use tokio::net::unix::SocketAddr;
+  use tokio::net::{UnixListener, UnixStream};
+
+  pub fn bind(conf: &ConnectionConfig, path: &str) -> std::io::Result<UnixListener> {
+    // clean up after a previous run, but never remove anything that isn't a socket
+    if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+      remove_file(path)?;
+    }
+
+    // bound in a private directory and moved into place once its permissions are set,
+    // so there's no window where anyone the umask allows can connect
+    let dir = format!("{}.{}", path, std::process::id());
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = format!("{dir}/s");
+    let bound = (|| {
+      let listener = UnixListener::bind(&tmp)?;
+      if let Some(mode) = conf.mode {
+        set_permissions(&tmp, Permissions::from_mode(mode))?;
+      }
+      if conf.owner.is_some() || conf.group.is_some() {
+        chown(&tmp, conf.owner, conf.group)?;
+      }
+      rename(&tmp, path)?;
+      Ok(listener)
+    })();
+    let _ = remove_file(&tmp);
+    let _ = remove_dir(&dir);
+    bound
+  }
+
+  /// Remove the socket files of every Unix socket listener.
+  pub fn remove_sockets() {
+    let paths = CONFIG.conn.iter().filter(|l| l.enabled);
+    for path in paths.filter_map(|l| l.path.as_deref()) {
+      if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        let _ = remove_file(path);
+      }
+    }
+  }
+
+  pub async fn handoff(
+    stream: UnixStream,
+    _: SocketAddr,
+    accept: bool,
+    conf: &'static ConnectionConfig,
+  ) {
+    let uid = stream.peer_cred().map(|c| c.uid()).ok();
+    let peer_user = uid.and_then(|uid| {
+      let name = conf.peer_users.get(&uid.to_string())?;
+      trace!(
+        "Peer credentials authenticated | uid: {}, user: {}",
+        uid,
+        name
+      );
+      User::get(name)
+    });
+    let peer_user = peer_user.map(|u| (u, "peer credentials"));
+    let trusted_user = conf.user.as_deref().and_then(User::get);
+    let user = peer_user.or(trusted_user.map(|u| (u, "listener")));
+    let peer = Peer::Unix { uid };
+    conn_handoff(&mut Connection::from(stream), accept, peer, user, conf).await;
+  }
+}
+
+#[inline]
+fn is_loopback(addr: &str) -> bool {
+  addr == "localhost" || addr.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+pub static TLS_ACCEPTOR: Global<TlsAcceptor> = Global::new();
 
 // SET UP LISTENERS
 
 pub async fn listen() {
   let conf = &*CONFIG;
+  let listeners = conf.conn.iter().filter(|l| l.enabled).collect::<Vec<_>>();
 
-  if !conf.conn.enabled {
+  if listeners.is_empty() {
     fatal!("No protocols enabled!");
   }
 
+  for l in &listeners {
+    if let Some(name) = &l.user {
+      if User::get(name).is_none() {
+        fatal!(
+          "Listener {} is trusted as unknown user `{}`",
+          l.addr(),
+          name
+        );
+      }
+    }
+    for name in l.peer_users.values() {
+      if User::get(name).is_none() {
+        fatal!(
+          "Listener {} maps peers to unknown user `{}`",
+          l.addr(),
+          name
+        );
+      }
+    }
+    if l.user.is_some() && l.path.is_none() && !is_loopback(&l.address) {
+      warn!(
+        "Listener {} trusts every connection as `{}` without AUTH, but isn't on a loopback address!",
+        l.addr(),
+        l.user.as_deref().unwrap_or_default()
+      );
+    }
+    if l.path.is_some() && l.tls {
+      fatal!(
+        "Listener {} is a Unix socket, which can't use TLS",
+        l.addr()
+      );
+    }
+    if l.require_client_cert && !(l.tls && conf.tls.as_ref().is_some_and(|t| t.client_ca.is_some()))
+    {
+      fatal!(
+        "Listener {} requires client certificates, but has no TLS `client_ca`",
+        l.addr()
+      );
+    }
+  }
+
+  let public = listeners
+    .iter()
+    .find(|l| l.path.is_none() && !is_loopback(&l.address));
+  if let Some(l) = public {
+    if !conf.allow_default_credentials && auth::default_credentials() {
+      fatal!(
+        "Refusing to listen on {} with the default credentials! Change the password for `{}` or set `allow_default_credentials`",
+        l.address,
+        auth::DEFAULT_USERNAME
+      );
+    }
+  }
+
   // SET UP TLS ACCEPTOR
 
-  if conf.conn.tls {
+  if listeners.iter().any(|l| l.tls) {
     if conf.tls.is_none() {
       fatal!("TLS is enabled but no TLS config was provided!");
     }
 
-    let tls = conf.tls.as_ref().unwrap();
-    let cert = wrap_fatal!(std::fs::read(&tls.cert), "Failed to parse TLS cert: {}");
-    let key = wrap_fatal!(std::fs::read(&tls.key), "Failed to parse TLS key: {}");
-    let identity = wrap_fatal!(
-      native_tls::Identity::from_pkcs8(&cert, &key),
-      "Failed to build TLS identity: {}"
-    );
-    let acceptor = wrap_fatal!(
-      native_tls::TlsAcceptor::new(identity),
-      "Failed to build TLS acceptor: {}"
-    );
-    TLS_ACCEPTOR.set(acceptor.into());
+    let tls_conf = conf.tls.as_ref().unwrap();
+    TLS_ACCEPTOR.set(TlsAcceptor::new(tls_conf));
+
+    tokio::spawn(tls::watch(&TLS_ACCEPTOR, tls_conf));
+    #[cfg(unix)]
+    tokio::spawn(tls::reload_on_sighup(&TLS_ACCEPTOR, tls_conf));
+  }
+
+  if conf.rate_limit.ip.is_some() || conf.rate_limit.user.is_some() {
+    tokio::spawn(ratelimit::cleanup());
+  }
+  if conf.auth_limit.ip_max_failures.is_some() {
+    tokio::spawn(auth::cleanup());
   }
 
   // START LISTENING FOR CONNECTIONS
 
-    tokio::spawn(listener!(
-      &format!("{}:{}", conf.conn.address, conf.conn.port),
-      conf.conn.tls,
-      conf.max_conns
-    ));
+  for l in listeners {
+    match &l.path {
+      #[cfg(unix)]
+      Some(path) => tokio::spawn(listener!(l, unix::bind(l, path), unix::handoff)),
+      #[cfg(not(unix))]
+      Some(_) => fatal!("Unix sockets are not supported on this platform!"),
+      None => tokio::spawn(listener!(l, TcpListener::bind(l.addr()).await, tcp_handoff)),
+    };
+  }
 }
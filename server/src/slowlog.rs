@@ -0,0 +1,128 @@
+use crate::auth::User;
+use crate::config::CONFIG;
+use crate::connection::Peer;
+use crate::metrics;
+use protocol::{Request, SlowRequest, Status};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+// SLOW LOG
+
+// newest at the back, never longer than `slowlog_size`
+static LOG: Mutex<VecDeque<SlowRequest>> = Mutex::new(VecDeque::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+pub fn enabled() -> bool {
+  CONFIG.slowlog_size > 0
+}
+
+fn record(req: SlowRequest) {
+  let mut log = LOG.lock().unwrap();
+  if log.len() >= CONFIG.slowlog_size {
+    log.pop_front();
+  }
+  log.push_back(req);
+}
+
+/// Up to `count` slow requests, newest first.
+pub fn get(count: Option<u64>) -> Vec<SlowRequest> {
+  let log = LOG.lock().unwrap();
+  let count = count.map_or(log.len(), |n| n as usize);
+  log.iter().rev().take(count).cloned().collect()
+}
+
+pub fn reset() {
+  LOG.lock().unwrap().clear();
+}
+
+// REQUEST TIMING
+
+// a table or key name, copied into a buffer that's reused between requests
+#[derive(Default)]
+struct Name {
+  buf: String,
+  set: bool,
+}
+
+impl Name {
+  #[inline]
+  fn copy(&mut self, name: Option<&str>) {
+    self.buf.clear();
+    self.set = name.is_some();
+    self.buf.push_str(name.unwrap_or_default());
+  }
+
+  #[inline]
+  fn get(&self) -> Option<String> {
+    self.set.then(|| self.buf.clone())
+  }
+}
+
+/// The request a connection is handling, recorded once it's answered.
+/// Kept for the whole connection, so only slow requests allocate.
+#[derive(Default)]
+pub struct Handling {
+  pub action: &'static str,
+  action_id: usize,
+  // unset between requests
+  start: Option<Instant>,
+  size: usize,
+  // waits for a change or a value, so how long it took says nothing about the server
+  blocking: bool,
+  // only copied when the slow log is enabled
+  table: Name,
+  key: Name,
+}
+
+impl Handling {
+  /// Start timing `req`, which arrived in a `size` byte frame.
+  pub fn start(&mut self, req: &Request, size: usize) {
+    self.action = req.action();
+    self.action_id = req.action_id();
+    self.start = Some(Instant::now());
+    self.size = size;
+    self.blocking = matches!(req, Request::Watch { .. } | Request::BlockingPop { .. });
+    if enabled() && !self.blocking {
+      self.table.copy(req.table());
+      self.key.copy(req.key());
+    }
+  }
+
+  /// Whether a request is being handled.
+  #[inline]
+  pub fn active(&self) -> bool {
+    self.start.is_some()
+  }
+
+  /// Record the request in metrics, and in the slow log if it took long enough.
+  /// Blocking requests are only counted.
+  pub fn finish(&mut self, peer: Peer, user: Option<User>, status: Status) {
+    let Some(start) = self.start.take() else {
+      return;
+    };
+    let took = (!self.blocking).then(|| start.elapsed());
+    metrics::request(self.action_id, status, took);
+
+    let Some(took) = took else {
+      return;
+    };
+    if !enabled() || took.as_micros() < CONFIG.slowlog_threshold as u128 {
+      return;
+    }
+    record(SlowRequest {
+      id: NEXT_ID.fetch_add(1, Relaxed),
+      time: SystemTime::now().checked_sub(took),
+      duration: took.as_micros() as u64,
+      action: self.action.to_owned(),
+      table: self.table.get(),
+      key: self.key.get(),
+      peer: peer.to_string(),
+      user: user.map(|u| u.name.to_owned()),
+      size: self.size as u64,
+    });
+  }
+}
@@ -0,0 +1,240 @@
+use crate::auth::User;
+use crate::config::{ClientIdentity, TlsConfig, CONFIG};
+use crate::{logger::*, wrap_fatal, AnyResult};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio::time::{interval, timeout, Duration};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("Either the `rustls` or `native-tls` feature must be enabled");
+
+#[cfg(all(feature = "rustls", feature = "native-tls"))]
+compile_error!("Only one of the `rustls` and `native-tls` features can be enabled, build rustls with `--no-default-features --features rustls`");
+
+// ACCEPTOR
+
+/// TLS acceptor for the backend selected at compile time.
+/// The inner acceptor can be swapped at runtime; established connections keep theirs.
+pub struct TlsAcceptor(RwLock<backend::Acceptor>);
+
+pub type TlsStream = backend::Stream;
+
+impl std::fmt::Debug for TlsAcceptor {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "TlsAcceptor({})", backend::NAME)
+  }
+}
+
+impl TlsAcceptor {
+  pub fn new(conf: &TlsConfig) -> Self {
+    info!("Using {} for TLS", backend::NAME);
+    Self(RwLock::new(wrap_fatal!(
+      backend::acceptor(conf),
+      "Failed to build TLS acceptor: {}"
+    )))
+  }
+
+  /// Rebuild the acceptor from `conf`, keeping the current one if that fails.
+  pub fn reload(&self, conf: &TlsConfig) -> AnyResult<()> {
+    let acceptor = backend::acceptor(conf)?;
+    *self.0.write().unwrap() = acceptor;
+    Ok(())
+  }
+
+  pub async fn accept(&self, stream: TcpStream) -> AnyResult<TlsStream> {
+    // cheap clone (Arc inside) so the lock isn't held across the handshake
+    let acceptor = self.0.read().unwrap().clone();
+    let handshake = acceptor.accept(stream);
+    match CONFIG.read_timeout {
+      0 => Ok(handshake.await?),
+      secs => Ok(timeout(Duration::from_secs(secs), handshake).await??),
+    }
+  }
+}
+
+// RELOADING
+
+fn reload(acceptor: &TlsAcceptor, conf: &TlsConfig) {
+  match acceptor.reload(conf) {
+    Ok(()) => info!("Reloaded TLS certificates"),
+    Err(e) => error!(
+      "Failed to reload TLS certificates, keeping the old ones: {}",
+      e
+    ),
+  }
+}
+
+// files whose changes trigger a reload
+fn modified(conf: &TlsConfig) -> Vec<Option<SystemTime>> {
+  let paths = [Some(&conf.cert), Some(&conf.key), conf.client_ca.as_ref()];
+  let paths = paths.into_iter().flatten();
+  paths
+    .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+    .collect()
+}
+
+/// Reload certificates when their files change, checking every `watch_interval` seconds.
+pub async fn watch(acceptor: &TlsAcceptor, conf: &TlsConfig) {
+  let Some(secs) = conf.watch_interval else {
+    return;
+  };
+  let mut interval = interval(Duration::from_secs(secs.max(1)));
+  let mut last = modified(conf);
+
+  loop {
+    interval.tick().await;
+    let current = modified(conf);
+    if current != last {
+      info!("TLS certificate files changed, reloading...");
+      reload(acceptor, conf);
+      last = current;
+    }
+  }
+}
+
+/// Reload certificates whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(acceptor: &TlsAcceptor, conf: &TlsConfig) {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let mut hangup = match signal(SignalKind::hangup()) {
+    Ok(s) => s,
+    Err(e) => {
+      error!("Failed to listen for SIGHUP: {}", e);
+      return;
+    }
+  };
+
+  while hangup.recv().await.is_some() {
+    info!("SIGHUP detected, reloading TLS certificates...");
+    reload(acceptor, conf);
+  }
+}
+
+/// Map a verified client certificate to a configured user, if any.
+pub fn client_user(stream: &TlsStream, conf: &TlsConfig) -> Option<User> {
+  let cert = backend::peer_certificate(stream)?;
+  let (_, cert) = parse_x509_certificate(&cert).ok()?;
+
+  // a certificate can carry several, the first one that is mapped wins
+  let identities: Vec<&str> = match conf.client_identity {
+    ClientIdentity::CommonName => {
+      let names = cert.subject().iter_common_name();
+      names.filter_map(|cn| cn.as_str().ok()).collect()
+    }
+    ClientIdentity::San => {
+      let san = cert.subject_alternative_name().ok()??;
+      let names = san.value.general_names.iter();
+      names
+        .filter_map(|name| match name {
+          GeneralName::DNSName(s) | GeneralName::RFC822Name(s) | GeneralName::URI(s) => Some(*s),
+          _ => None,
+        })
+        .collect()
+    }
+  };
+
+  // only explicitly mapped identities authenticate, anyone the CA signed for could pick their name
+  let mapped = identities
+    .iter()
+    .find_map(|id| conf.client_users.get_key_value(*id));
+  let Some((identity, name)) = mapped else {
+    warn!("Client certificate {:?} does not map to a user", identities);
+    return None;
+  };
+  let user = User::get(name);
+  match user {
+    Some(_) => trace!("Client certificate authenticated | user: {}", name),
+    None => warn!(
+      "Client certificate {} maps to unknown user {}",
+      identity, name
+    ),
+  }
+  user
+}
+
+// RUSTLS
+
+#[cfg(feature = "rustls")]
+mod backend {
+  use super::*;
+  use std::sync::Arc;
+  use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+  use tokio_rustls::rustls::server::WebPkiClientVerifier;
+  use tokio_rustls::rustls::version::TLS13;
+  use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+
+  pub const NAME: &str = "rustls";
+  pub type Acceptor = tokio_rustls::TlsAcceptor;
+  pub type Stream = tokio_rustls::server::TlsStream<TcpStream>;
+
+  pub fn acceptor(conf: &TlsConfig) -> AnyResult<Acceptor> {
+    let builder = if conf.tls13_only {
+      ServerConfig::builder_with_protocol_versions(&[&TLS13])
+    } else {
+      ServerConfig::builder()
+    };
+
+    let builder = if let Some(ca) = &conf.client_ca {
+      info!("Client certificate verification enabled");
+      if conf.client_users.is_empty() {
+        warn!("No `client_users` are set, so client certificates won't authenticate anyone");
+      }
+      let mut roots = RootCertStore::empty();
+      for cert in CertificateDer::pem_file_iter(ca)? {
+        roots.add(cert?)?;
+      }
+      let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+      if conf.client_cert_optional {
+        verifier = verifier.allow_unauthenticated();
+      }
+      builder.with_client_cert_verifier(verifier.build()?)
+    } else {
+      builder.with_no_client_auth()
+    };
+
+    // full chain, leaf first; the key may be PKCS#8, PKCS#1 (RSA) or SEC1 (ECDSA)
+    let chain = CertificateDer::pem_file_iter(&conf.cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(&conf.key)?;
+
+    let mut server_conf = builder.with_single_cert(chain, key)?;
+    server_conf.alpn_protocols = conf.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+    Ok(Arc::new(server_conf).into())
+  }
+
+  pub fn peer_certificate(stream: &Stream) -> Option<Vec<u8>> {
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    Some(cert.to_vec())
+  }
+}
+
+// NATIVE-TLS
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod backend {
+  use super::*;
+  use tokio_native_tls::native_tls;
+
+  pub const NAME: &str = "native-tls";
+  pub type Acceptor = tokio_native_tls::TlsAcceptor;
+  pub type Stream = tokio_native_tls::TlsStream<TcpStream>;
+
+  pub fn acceptor(conf: &TlsConfig) -> AnyResult<Acceptor> {
+    if conf.client_ca.is_some() || conf.tls13_only || !conf.alpn.is_empty() {
+      // native-tls has no way to request or verify client certificates
+      return Err("client_ca, tls13_only and alpn require the `rustls` feature".into());
+    }
+
+    let cert = std::fs::read(&conf.cert)?;
+    let key = std::fs::read(&conf.key)?;
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key)?;
+    Ok(native_tls::TlsAcceptor::new(identity)?.into())
+  }
+
+  pub fn peer_certificate(stream: &Stream) -> Option<Vec<u8>> {
+    stream.get_ref().peer_certificate().ok()??.to_der().ok()
+  }
+}
@@ -61,6 +61,42 @@ impl<T: Debug> Default for Global<T> {
   }
 }
 
+// PATTERNS
+
+/// Match `str` against a glob-style `pattern`, where `*` matches any run of
+/// characters and `?` matches any single character.
+pub fn glob_match(pattern: &str, str: &str) -> bool {
+  // byte offsets, always on character boundaries
+  let (mut p, mut s) = (0, 0);
+  // position of the last `*` in pattern, and where in str it started matching
+  let mut star: Option<(usize, usize)> = None;
+
+  while let Some(c) = str[s..].chars().next() {
+    match pattern[p..].chars().next() {
+      Some('*') => {
+        star = Some((p, s));
+        p += 1;
+      }
+      Some(pc) if pc == '?' || pc == c => {
+        p += pc.len_utf8();
+        s += c.len_utf8();
+      }
+      _ => match star {
+        // backtrack: let the last `*` swallow one more character
+        Some((sp, ss)) => {
+          let skipped = str[ss..].chars().next().map_or(1, char::len_utf8);
+          p = sp + 1;
+          s = ss + skipped;
+          star = Some((sp, s));
+        }
+        None => return false,
+      },
+    }
+  }
+
+  pattern[p..].chars().all(|c| c == '*')
+}
+
 // HASHER
 
 #[cfg(feature = "gxhash")]
@@ -70,3 +106,62 @@ pub type Hasher = std::collections::hash_map::RandomState;
 
 pub type SyncHashMap<K, V> = std::collections::HashMap<K, V, Hasher>;
 pub type SyncHashSet<T> = std::collections::HashSet<T, Hasher>;
+
+#[cfg(test)]
+mod tests {
+  use super::glob_match;
+
+  #[test]
+  fn glob_literals() {
+    assert!(glob_match("", ""));
+    assert!(glob_match("users", "users"));
+    assert!(!glob_match("users", "user"));
+    assert!(!glob_match("user", "users"));
+    assert!(!glob_match("", "users"));
+  }
+
+  #[test]
+  fn glob_stars() {
+    assert!(glob_match("*", ""));
+    assert!(glob_match("*", "anything"));
+    assert!(glob_match("user*", "users"));
+    assert!(glob_match("user*", "user"));
+    assert!(glob_match("*s", "users"));
+    assert!(glob_match("u*r*s", "users"));
+    assert!(glob_match("**", "users"));
+    assert!(!glob_match("user*", "admin"));
+    assert!(!glob_match("*x", "users"));
+  }
+
+  #[test]
+  fn glob_backtracks() {
+    assert!(glob_match("*ab", "aaab"));
+    assert!(glob_match("a*b*c", "abbbbc"));
+    assert!(glob_match("*a*b", "xaxbxab"));
+    assert!(!glob_match("a*b*c", "abbbb"));
+    assert!(!glob_match("*ab", "abba"));
+  }
+
+  #[test]
+  fn glob_question_marks() {
+    assert!(glob_match("?", "a"));
+    assert!(glob_match("us?rs", "users"));
+    assert!(glob_match("?*", "a"));
+    assert!(!glob_match("?", ""));
+    assert!(!glob_match("??", "a"));
+  }
+
+  #[test]
+  fn glob_matches_characters_not_bytes() {
+    assert!(glob_match("?", "é"));
+    assert!(glob_match("caf?", "café"));
+    assert!(glob_match("???", "日本語"));
+    assert!(!glob_match("??", "日"));
+    assert!(glob_match("*語", "日本語"));
+    assert!(glob_match("日*", "日本語"));
+    assert!(glob_match("*本*", "日本語"));
+    assert!(!glob_match("*本", "日本語"));
+    assert!(glob_match("é", "é"));
+    assert!(!glob_match("é", "e"));
+  }
+}
@@ -0,0 +1,173 @@
+use crate::{metrics, pubsub, DATABASE};
+use protocol::{KeyspaceAction, Payload, Response, Status::*};
+
+use scc::HashMap;
+use std::future::pending;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tokio::time::{sleep_until, Instant};
+
+// WATCHERS
+
+// watcher id and where to send the first change
+type Watchers = Vec<(u64, Sender<KeyspaceAction>)>;
+
+// (table, key) -> watchers
+static WATCHERS: LazyLock<HashMap<(String, String), Watchers>> = LazyLock::new(HashMap::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+// checked first, so changes cost nothing extra while nothing is watched
+static WATCHING: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+pub fn watching() -> bool {
+  WATCHING.load(Relaxed) > 0
+}
+
+fn wake(watchers: Watchers, action: KeyspaceAction) {
+  for (_, tx) in watchers {
+    let _ = tx.send(action);
+  }
+}
+
+/// Wake every watcher of `key` in `table`, or of any key in `table` if it was deleted.
+pub async fn changed(action: KeyspaceAction, table: &str, key: Option<&str>) {
+  if WATCHING.load(Relaxed) == 0 {
+    return;
+  }
+  match (action, key) {
+    (KeyspaceAction::DeleteTable, _) => {
+      WATCHERS
+        .retain_async(|(t, _), watchers| {
+          if t != table {
+            return true;
+          }
+          wake(std::mem::take(watchers), action);
+          false
+        })
+        .await
+    }
+    (_, Some(key)) => {
+      let watched = (table.to_owned(), key.to_owned());
+      if let Some((_, watchers)) = WATCHERS.remove_async(&watched).await {
+        wake(watchers, action);
+      }
+    }
+    // a new table can't have been watched, WATCH needs it to exist
+    (_, None) => {}
+  }
+}
+
+/// Waits for the first change to a key, unregistered when dropped.
+pub struct Watcher {
+  id: u64,
+  watched: (String, String),
+  rx: Receiver<KeyspaceAction>,
+}
+
+impl Watcher {
+  pub async fn new(table: String, key: String) -> Self {
+    let id = NEXT_ID.fetch_add(1, Relaxed);
+    let (tx, rx) = channel();
+    let watched = (table, key);
+    WATCHING.fetch_add(1, Relaxed);
+    let mut entry = WATCHERS.entry_async(watched.clone()).await.or_default();
+    entry.get_mut().push((id, tx));
+    Self { id, watched, rx }
+  }
+
+  /// Wait for the change. Cancel safe.
+  pub async fn changed(&mut self) -> KeyspaceAction {
+    match (&mut self.rx).await {
+      Ok(action) => action,
+      // the sender is only dropped after sending
+      Err(_) => pending().await,
+    }
+  }
+}
+
+impl Drop for Watcher {
+  fn drop(&mut self) {
+    WATCHING.fetch_sub(1, Relaxed);
+    // still registered if we gave up before anything changed
+    WATCHERS.update(&self.watched, |_, w| w.retain(|(i, _)| *i != self.id));
+    WATCHERS.remove_if(&self.watched, |w| w.is_empty());
+  }
+}
+
+// EXPIRY
+
+// when `key` expires, if it exists and has a lifetime
+async fn expiry(table: &str, key: &str) -> Option<Instant> {
+  let tbl = DATABASE.get_async(table).await?;
+  let expiry = tbl.read_async(key, |_, v| v.expiry).await??;
+  let left = expiry.duration_since(SystemTime::now()).unwrap_or_default();
+  // too far off to ever happen
+  Instant::now().checked_add(left)
+}
+
+// remove `key` if it has expired, like GET does
+async fn expire(table: &str, key: &str) {
+  let Some(tbl) = DATABASE.get_async(table).await else {
+    return;
+  };
+  let now = SystemTime::now();
+  let expired = tbl.remove_if_async(key, |v| v.expiry.is_some_and(|st| st <= now));
+  if expired.await.is_some() {
+    drop(tbl);
+    metrics::EXPIRED_KEYS.fetch_add(1, Relaxed);
+    pubsub::notify(KeyspaceAction::Expire, table, Some(key)).await;
+  }
+}
+
+/// Sleep until `deadline`, or forever if there isn't one.
+pub async fn until(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => sleep_until(deadline).await,
+    None => pending().await,
+  }
+}
+
+// WATCH
+
+/// Wait until `key` in `table` is created, modified, deleted or expires, or `timeout` seconds pass.
+/// Responds with the new value, or the status a GET would get.
+pub async fn watch(table: String, key: String, timeout: Option<u64>) -> Response {
+  // registered before reading the key, so no change can be missed
+  let mut watcher = Watcher::new(table, key).await;
+  let (table, key) = watcher.watched.clone();
+  if !DATABASE.contains_async(&table).await {
+    return Response::status(NoSuchTable);
+  }
+
+  // a timeout too long to represent never ends
+  let timeout = timeout.and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs)));
+  let action = loop {
+    let expiry = expiry(&table, &key).await;
+    tokio::select! {
+      biased;
+      action = watcher.changed() => break action,
+      // wakes this watcher, unless the key changed in the meantime, which also does
+      _ = until(expiry) => expire(&table, &key).await,
+      _ = until(timeout) => return Response::status(WaitTimedOut),
+    }
+  };
+
+  match action {
+    KeyspaceAction::Expire => Response::status(KeyExpired),
+    KeyspaceAction::DeleteTable => Response::status(NoSuchTable),
+    KeyspaceAction::Delete => Response::status(NoSuchKey),
+    // the latest value, which may have changed again since
+    _ => match DATABASE.get_async(&table).await {
+      Some(tbl) => match tbl.get_async(&key).await {
+        Some(value) => {
+          let value = value.clone();
+          Response::ok(Payload::TableValue { table, key, value })
+        }
+        None => Response::status(NoSuchKey),
+      },
+      None => Response::status(NoSuchTable),
+    },
+  }
+}